@@ -0,0 +1,150 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `#[derive(KernelArgs)]` for `compute-shader`.
+//!
+//! See the `compute_shader::kernel_args` module for how to use it.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::{Data, DeriveInput, Fields, Lit, Meta, NestedMeta};
+
+#[proc_macro_derive(KernelArgs, attributes(uniform))]
+pub fn derive_kernel_args(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let fields = match input.data {
+        Data::Struct(ref data) => {
+            match data.fields {
+                Fields::Named(ref fields) => &fields.named,
+                _ => {
+                    return Err(syn::Error::new(Span::call_site(),
+                                               "`KernelArgs` requires a struct with named fields"))
+                }
+            }
+        }
+        _ => return Err(syn::Error::new(Span::call_site(), "`KernelArgs` requires a struct")),
+    };
+
+    let mut names = vec![];
+    let mut uniforms = vec![];
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let name = match try!(uniform_name(&field.attrs)) {
+            Some(name) => name,
+            None => ident.to_string(),
+        };
+        if names.contains(&name) {
+            return Err(syn::Error::new_spanned(field,
+                                               format!("more than one field is passed as `{}`",
+                                                       name)))
+        }
+        names.push(name.clone());
+        uniforms.push(quote! {
+            (#name, ::compute_shader::kernel_args::AsUniform::as_uniform(&self.#ident))
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::compute_shader::kernel_args::KernelArgs for #ident #ty_generics
+                #where_clause {
+            fn uniforms<'__uniform>(&'__uniform self)
+                                    -> Vec<(&'static str,
+                                            ::compute_shader::queue::Uniform<'__uniform>)> {
+                vec![#(#uniforms),*]
+            }
+        }
+    })
+}
+
+// Parses `#[uniform(name = "...")]`, which overrides the program argument a field is passed as.
+fn uniform_name(attrs: &[syn::Attribute]) -> Result<Option<String>, syn::Error> {
+    for attr in attrs {
+        if !attr.path.is_ident("uniform") {
+            continue
+        }
+
+        let nested_meta = match try!(attr.parse_meta()) {
+            Meta::List(ref meta_list) if meta_list.nested.len() == 1 => meta_list.nested[0].clone(),
+            meta => return Err(syn::Error::new_spanned(meta, "expected `uniform(name = \"...\")`")),
+        };
+        match nested_meta {
+            NestedMeta::Meta(Meta::NameValue(ref name_value)) if name_value.ident == "name" => {
+                if let Lit::Str(ref name) = name_value.lit {
+                    return Ok(Some(name.value()))
+                }
+                return Err(syn::Error::new_spanned(&name_value.lit, "expected a string"))
+            }
+            nested_meta => {
+                return Err(syn::Error::new_spanned(nested_meta, "expected `name = \"...\"`"))
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+    use syn::DeriveInput;
+
+    fn expand_str(input: &str) -> Result<String, String> {
+        let input: DeriveInput = syn::parse_str(input).unwrap();
+        expand(&input).map(|tokens| tokens.to_string()).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn passes_fields_by_name() {
+        let tokens = expand_str("struct Args<'a> { image: &'a Image, seed: u32 }").unwrap();
+        assert!(tokens.contains("KernelArgs for Args < 'a >"));
+        assert!(tokens.contains("\"image\""));
+        assert!(tokens.contains("\"seed\""));
+    }
+
+    #[test]
+    fn renames_fields() {
+        let tokens = expand_str("struct Args { #[uniform(name = \"kSeed\")] seed: u32 }").unwrap();
+        assert!(tokens.contains("\"kSeed\""));
+        assert!(!tokens.contains("\"seed\""));
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let error = expand_str("struct Args { a: u32, #[uniform(name = \"a\")] b: u32 }");
+        assert_eq!(error.unwrap_err(), "more than one field is passed as `a`");
+    }
+
+    #[test]
+    fn rejects_malformed_attributes() {
+        assert!(expand_str("struct Args { #[uniform(name = 1)] a: u32 }").is_err());
+        assert!(expand_str("struct Args { #[uniform(index = \"a\")] a: u32 }").is_err());
+        assert!(expand_str("struct Args { #[uniform] a: u32 }").is_err());
+    }
+
+    #[test]
+    fn rejects_non_structs() {
+        assert!(expand_str("struct Args(u32);").is_err());
+        assert!(expand_str("enum Args { A }").is_err());
+    }
+}
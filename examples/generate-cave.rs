@@ -2,16 +2,17 @@
  * http://creativecommons.org/publicdomain/zero/1.0/ */
 
 extern crate compute_shader;
+#[macro_use]
+extern crate compute_shader_derive;
 extern crate euclid;
 extern crate gl;
 extern crate glfw;
 extern crate lord_drawquaad;
 extern crate rand;
 
-use compute_shader::buffer::{BufferData, Protection};
-use compute_shader::image::{ExternalImage, Format};
+use compute_shader::buffer::{Buffer, BufferData, Protection};
+use compute_shader::image::{ExternalImage, Format, Image};
 use compute_shader::instance::{Instance, ShadingLanguage};
-use compute_shader::queue::Uniform;
 use euclid::Size2D;
 use gl::types::GLint;
 use glfw::{Action, Context, Key, OpenGlProfileHint, WindowEvent};
@@ -25,6 +26,19 @@ const HEIGHT: u32 = 600;
 
 const ITERATIONS: u32 = 8;
 
+// The arguments to the cave generator. Both versions of the program use these names.
+#[derive(KernelArgs)]
+struct CaveArgs<'a> {
+    #[uniform(name = "gImage")]
+    image: &'a Image,
+    #[uniform(name = "gBuffer")]
+    buffer: &'a Buffer,
+    #[uniform(name = "kSeed")]
+    seed: u32,
+    #[uniform(name = "kIterations")]
+    iterations: u32,
+}
+
 #[derive(Clone, Copy, Debug)]
 struct Vertex {
     position: [f32; 2],
@@ -76,14 +90,14 @@ pub fn main() {
     }
 
     let groups = [WIDTH, HEIGHT];
-    let uniforms = [
-        (0, Uniform::Image(&dest)),
-        (1, Uniform::Buffer(&buffer)),
-        (2, Uniform::U32(seed)),
-        (3, Uniform::U32(ITERATIONS)),
-    ];
+    let args = CaveArgs {
+        image: &dest,
+        buffer: &buffer,
+        seed: seed,
+        iterations: ITERATIONS,
+    };
     let queue = device.create_queue().unwrap();
-    queue.submit_compute(&program, &groups, &uniforms, &[]).unwrap();
+    queue.submit_compute_with_args(&program, &groups, &args, &[]).unwrap();
     queue.submit_sync_event().unwrap().wait().unwrap();
 
     unsafe {
@@ -142,7 +156,7 @@ static CL_SHADER: &'static str = r#"
     }
 
     __kernel void generate_caves(__write_only image2d_t gImage,
-                                 __global uchar *buffer,
+                                 __global uchar *gBuffer,
                                  uint kSeed,
                                  uint kIterations) {
         // Based on xxHash
@@ -156,11 +170,11 @@ static CL_SHADER: &'static str = r#"
         int2 dimensions = get_image_dim(gImage);
         int2 home = (int2)((int)get_global_id(0), (int)get_global_id(1));
         bool on = rand(state) < 0x73333333;
-        buffer[offset(home, dimensions)] = value(on);
+        gBuffer[offset(home, dimensions)] = value(on);
 
         for (uint i = 0; i < kIterations; i++) {
             barrier(CLK_GLOBAL_MEM_FENCE);
-            uint neighbors = countNeighbors(buffer, home, dimensions);
+            uint neighbors = countNeighbors(gBuffer, home, dimensions);
 
             // Verbosity to work around an LLVM bug.
             if (on && neighbors < 3)
@@ -169,7 +183,7 @@ static CL_SHADER: &'static str = r#"
                 on = true;
 
             barrier(CLK_GLOBAL_MEM_FENCE);
-            buffer[offset(home, dimensions)] = value(on);
+            gBuffer[offset(home, dimensions)] = value(on);
         }
 
         uint4 color = (uint4)((uint)value(on), (uint)value(on), (uint)value(on), (uint)value(on));
@@ -186,14 +200,14 @@ static GL_SHADER: &'static str = r#"
     #extension GL_ARB_shader_image_size : require
     #extension GL_ARB_shading_language_420pack : require
 
-    layout(r8, binding = 0) uniform restrict writeonly image2D uImage;
+    layout(r8, binding = 0) uniform restrict writeonly image2D gImage;
 
-    layout(std430, binding = 1) buffer ssbBuffer {
-        float gBuffer[480000];
+    layout(std430, binding = 1) buffer gBuffer {
+        float gCells[480000];
     };
 
-    layout(location = 2) uniform uint uSeed;
-    layout(location = 3) uniform uint uIterations;
+    layout(location = 2) uniform uint kSeed;
+    layout(location = 3) uniform uint kIterations;
 
     layout(local_size_x = 32, local_size_y = 32, local_size_z = 1) in;
 
@@ -219,7 +233,7 @@ static GL_SHADER: &'static str = r#"
             if (y >= 0 && y < dimensions.y) {
                 for (int x = p.x - 1; x <= p.x + 1; x++) {
                     if (x >= 0 && x < dimensions.x && (y != p.y || x != p.x)) {
-                        if (gBuffer[offset(ivec2(x, y), dimensions)] != 0.0)
+                        if (gCells[offset(ivec2(x, y), dimensions)] != 0.0)
                             neighbors++;
                     }
                 }
@@ -230,21 +244,21 @@ static GL_SHADER: &'static str = r#"
 
     void main() {
         // Based on xxHash
-        uint state = uSeed;
+        uint state = kSeed;
         state *= uint(gl_GlobalInvocationID.x);
         state ^= state >> 13u;
         state *= uint(gl_GlobalInvocationID.y);
         state ^= state >> 16u;
 
         // Initial state
-        ivec2 dimensions = imageSize(uImage);
+        ivec2 dimensions = imageSize(gImage);
         ivec2 home = ivec2(gl_GlobalInvocationID.xy);
         bool inBounds = home.x < dimensions.x && home.y < dimensions.y;
         bool on = rand(state) < 0x73333333u;
         if (inBounds)
-            gBuffer[offset(home, dimensions)] = value(on);
+            gCells[offset(home, dimensions)] = value(on);
 
-        for (uint i = 0u; i < uIterations; i++) {
+        for (uint i = 0u; i < kIterations; i++) {
             barrier();
             uint neighbors = inBounds ? countNeighbors(home, dimensions) : 0u;
 
@@ -255,12 +269,12 @@ static GL_SHADER: &'static str = r#"
 
             barrier();
             if (inBounds)
-                gBuffer[offset(home, dimensions)] = value(on);
+                gCells[offset(home, dimensions)] = value(on);
         }
 
         vec4 color = vec4(value(on));
         if (inBounds)
-            imageStore(uImage, home, color);
+            imageStore(gImage, home, color);
     }
 "#;
 
@@ -1,21 +1,17 @@
 /* Any copyright is dedicated to the Public Domain.
  * http://creativecommons.org/publicdomain/zero/1.0/ */
 
-extern crate byteorder;
 extern crate compute_shader;
 extern crate gl;
 extern crate glfw;
 extern crate rand;
 
-use byteorder::{NativeEndian, ReadBytesExt};
-use compute_shader::buffer::{BufferData, HostAllocatedData, Protection};
+use compute_shader::buffer::Protection;
 use compute_shader::instance::{Instance, ShadingLanguage};
 use compute_shader::queue::Uniform;
 use glfw::{Context, OpenGlProfileHint, WindowHint, WindowMode};
 use rand::Rng;
 use std::env;
-use std::io::Cursor;
-use std::mem;
 use std::os::raw::c_void;
 
 const DEFAULT_MATRIX_LENGTH: usize = 512;
@@ -59,30 +55,20 @@ pub fn main() {
     println!("Input:");
     print(&input, matrix_length);
 
-    let input = BufferData::HostAllocated(HostAllocatedData::new(&input));
-    let input = device.create_buffer(Protection::ReadOnly, input).unwrap();
-    let output = BufferData::Uninitialized(matrix_length * matrix_length * mem::size_of::<f32>());
-    let output = device.create_buffer(Protection::WriteOnly, output).unwrap();
+    let input = device.create_buffer_from_slice(Protection::ReadOnly, &input).unwrap();
+    let output = device.create_typed_buffer::<f32>(Protection::WriteOnly,
+                                                   matrix_length * matrix_length).unwrap();
 
     let queue = device.create_queue().unwrap();
     let groups = [matrix_length as u32, matrix_length as u32];
     let uniforms = [
-        (0, Uniform::Buffer(&output)),
-        (1, Uniform::Buffer(&input)),
+        (0, Uniform::Buffer(output.buffer())),
+        (1, Uniform::Buffer(input.buffer())),
         (2, Uniform::U32(matrix_length as u32)),
     ];
     queue.submit_compute(&program, &groups, &uniforms, &[]).unwrap();
-    let event = queue.submit_sync_event().unwrap();
 
-    let mut result_bytes = vec![0; matrix_length * matrix_length * mem::size_of::<f32>()];
-    queue.submit_read_buffer(&mut result_bytes, &output, 0, &[event]).unwrap();
-    queue.submit_sync_event().unwrap().wait().unwrap();
-
-    let mut result = Vec::with_capacity(matrix_length * matrix_length);
-    let mut result_cursor = Cursor::new(result_bytes);
-    while let Ok(value) = result_cursor.read_f32::<NativeEndian>() {
-        result.push(value)
-    }
+    let result = output.read_to_vec(&queue, &[]).unwrap();
 
     println!("\nResult:");
     print(&result, matrix_length);
@@ -8,14 +8,330 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::cl::ffi::{self, cl_mem};
-use buffer::{Buffer, BufferFunctions};
+use api::cl::ffi::{self, CL_MAP_READ, CL_MAP_WRITE, CL_MAP_WRITE_INVALIDATE_REGION, CL_SUCCESS};
+use api::cl::ffi::{CL_MEM_WRITE_ONLY, CL_QUEUE_CONTEXT, CL_TRUE, cl_context, cl_mem};
+use api::cl::ffi::{CL_BUFFER_CREATE_TYPE_REGION, CL_MISALIGNED_SUB_BUFFER_OFFSET};
+use api::cl::ffi::{cl_buffer_region, cl_event, cl_uint};
+use api::cl::queue as cl_queue;
+use api::gl::buffer as gl_buffer;
+use buffer::{Buffer, BufferFunctions, BufferSlice, ExternalBuffer, MapMode};
+use error::Error;
+use queue::Queue;
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::Range;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex};
 
 pub static BUFFER_FUNCTIONS: BufferFunctions = BufferFunctions {
     destroy: destroy,
+    map: map,
+    unmap: unmap,
+    copy_to: copy_to,
 };
 
+// Buffers created with `CL_MEM_USE_HOST_PTR` over caller-owned memory.
+pub static HOST_BUFFER_FUNCTIONS: BufferFunctions = BufferFunctions {
+    destroy: destroy_host_buffer,
+    map: map,
+    unmap: unmap,
+    copy_to: copy_to,
+};
+
+// Buffers created from OpenGL buffers owned by the caller. OpenCL must acquire these from OpenGL
+// while they are mapped or copied from.
+pub static IMPORTED_BUFFER_FUNCTIONS: BufferFunctions = BufferFunctions {
+    destroy: destroy,
+    map: map_imported,
+    unmap: unmap_imported,
+    copy_to: copy_to,
+};
+
+// The sub-buffers created for buffer slices passed to programs, keyed by parent buffer, offset,
+// and length, along with when each was last used. Each is kept so that passing the same slice
+// again needn't create another, until its parent buffer is destroyed or it is evicted to make room
+// for a newer one.
+static SUB_BUFFERS: Mutex<SubBuffers> = Mutex::new(SubBuffers {
+    sub_buffers: BTreeMap::new(),
+    clock: 0,
+});
+
+// Buffer pools hand out new slices of the same long-lived buffers all the time, so the sub-buffers
+// of each buffer must be bounded.
+const MAX_SUB_BUFFERS_PER_BUFFER: usize = 64;
+
+struct SubBuffers {
+    sub_buffers: BTreeMap<(usize, usize, usize), SubBuffer>,
+    clock: u64,
+}
+
+struct SubBuffer {
+    mem: usize,
+    last_used: u64,
+}
+
+// Set by the destructor callback of a host buffer once OpenCL no longer uses its memory.
+type Destroyed = (Mutex<bool>, Condvar);
+
 unsafe fn destroy(this: &Buffer) {
+    release_sub_buffers(this);
     ffi::clReleaseMemObject(this.data() as cl_mem);
 }
 
+// The caller may free the memory as soon as this returns, so wait until OpenCL has actually
+// destroyed the buffer, which happens only once no enqueued command uses it. Commands that were
+// never flushed would never complete, so flush every queue first, since any of them may have used
+// the buffer.
+unsafe fn destroy_host_buffer(this: &Buffer) {
+    release_sub_buffers(this);
+    cl_queue::flush_all();
+
+    let destroyed: Arc<Destroyed> = Arc::new((Mutex::new(false), Condvar::new()));
+    let user_data = Arc::into_raw(destroyed.clone()) as *mut c_void;
+    if ffi::clSetMemObjectDestructorCallback(this.data() as cl_mem,
+                                             Some(notify_destroyed),
+                                             user_data) != CL_SUCCESS {
+        drop(Arc::from_raw(user_data as *const Destroyed));
+        ffi::clReleaseMemObject(this.data() as cl_mem);
+        return
+    }
+
+    ffi::clReleaseMemObject(this.data() as cl_mem);
+
+    let mut is_destroyed = destroyed.0.lock().unwrap();
+    while !*is_destroyed {
+        is_destroyed = destroyed.1.wait(is_destroyed).unwrap();
+    }
+}
+
+/// Returns true if `buffer` was created from an OpenGL buffer.
+pub fn is_imported(buffer: &Buffer) -> bool {
+    ptr::eq(buffer.functions(), &IMPORTED_BUFFER_FUNCTIONS)
+}
+
+/// Returns a sub-buffer covering `slice`, creating it the first time the slice is used.
+///
+/// The sub-buffer belongs to the parent buffer and must not be released by the caller.
+pub fn sub_buffer(slice: &BufferSlice) -> Result<cl_mem, Error> {
+    let parent = slice.buffer().data();
+    let key = (parent, slice.offset(), slice.len());
+    let mut sub_buffers = SUB_BUFFERS.lock().unwrap();
+    sub_buffers.clock += 1;
+    let now = sub_buffers.clock;
+    if let Some(sub_buffer) = sub_buffers.sub_buffers.get_mut(&key) {
+        sub_buffer.last_used = now;
+        return Ok(sub_buffer.mem as cl_mem)
+    }
+
+    let region = cl_buffer_region {
+        origin: slice.offset(),
+        size: slice.len(),
+    };
+    let mut err = CL_SUCCESS;
+    let mem = unsafe {
+        ffi::clCreateSubBuffer(parent as cl_mem,
+                               0,
+                               CL_BUFFER_CREATE_TYPE_REGION,
+                               &region as *const cl_buffer_region as *const c_void,
+                               &mut err)
+    };
+    match err {
+        CL_SUCCESS => {}
+        CL_MISALIGNED_SUB_BUFFER_OFFSET => return Err(Error::Misaligned),
+        _ => return Err(Error::Failed),
+    }
+
+    // Commands already enqueued keep the sub-buffers they use alive, so the least recently used
+    // one can be released right away.
+    let (count, least_recently_used) = {
+        let siblings = sub_buffers.sub_buffers.range((parent, 0, 0)..(parent + 1, 0, 0));
+        let count = siblings.clone().count();
+        let least_recently_used = siblings.min_by_key(|&(_, sub_buffer)| sub_buffer.last_used)
+                                          .map(|(&key, _)| key);
+        (count, least_recently_used)
+    };
+    if count >= MAX_SUB_BUFFERS_PER_BUFFER {
+        if let Some(evicted) = least_recently_used.and_then(|key| {
+            sub_buffers.sub_buffers.remove(&key)
+        }) {
+            unsafe {
+                ffi::clReleaseMemObject(evicted.mem as cl_mem);
+            }
+        }
+    }
+
+    sub_buffers.sub_buffers.insert(key, SubBuffer {
+        mem: mem as usize,
+        last_used: now,
+    });
+    Ok(mem)
+}
+
+// Commands already enqueued keep the sub-buffers they use alive, so these can be released as soon
+// as the parent buffer is destroyed.
+unsafe fn release_sub_buffers(buffer: &Buffer) {
+    let data = buffer.data();
+    let mut sub_buffers = SUB_BUFFERS.lock().unwrap();
+    let keys: Vec<_> = sub_buffers.sub_buffers
+                                  .range((data, 0, 0)..(data + 1, 0, 0))
+                                  .map(|(&key, _)| key)
+                                  .collect();
+    for key in keys {
+        if let Some(sub_buffer) = sub_buffers.sub_buffers.remove(&key) {
+            ffi::clReleaseMemObject(sub_buffer.mem as cl_mem);
+        }
+    }
+}
+
+unsafe extern "C" fn notify_destroyed(_: cl_mem, user_data: *mut c_void) {
+    let destroyed = Arc::from_raw(user_data as *const Destroyed);
+    *destroyed.0.lock().unwrap() = true;
+    destroyed.1.notify_all();
+}
+
+fn map(this: &Buffer, queue: &Queue, range: Range<usize>, mode: MapMode)
+       -> Result<*mut u8, Error> {
+    unsafe {
+        map_after(this, queue, range, mode, &[])
+    }
+}
+
+// Maps the buffer once the events in `wait_list` have completed.
+unsafe fn map_after(this: &Buffer,
+                    queue: &Queue,
+                    range: Range<usize>,
+                    mode: MapMode,
+                    wait_list: &[cl_event])
+                    -> Result<*mut u8, Error> {
+    let flags = match mode {
+        MapMode::Read => CL_MAP_READ,
+        MapMode::Write => CL_MAP_WRITE_INVALIDATE_REGION,
+        MapMode::ReadWrite => CL_MAP_READ | CL_MAP_WRITE,
+    };
+
+    let mut error = CL_SUCCESS;
+    let ptr = ffi::clEnqueueMapBuffer(cl_queue::cl_command_queue(queue),
+                                      this.data() as cl_mem,
+                                      CL_TRUE,
+                                      flags,
+                                      range.start,
+                                      range.end - range.start,
+                                      wait_list.len() as cl_uint,
+                                      cl_queue::event_wait_list_ptr(wait_list),
+                                      ptr::null_mut(),
+                                      &mut error);
+    if error == CL_SUCCESS && !ptr.is_null() {
+        Ok(ptr as *mut u8)
+    } else {
+        Err(Error::Failed)
+    }
+}
+
+unsafe fn unmap(this: &Buffer, queue: &Queue, ptr: *mut u8) {
+    ffi::clEnqueueUnmapMemObject(cl_queue::cl_command_queue(queue),
+                                 this.data() as cl_mem,
+                                 ptr as *mut c_void,
+                                 0,
+                                 ptr::null(),
+                                 ptr::null_mut());
+}
+
+// The buffer stays acquired from OpenGL until it is unmapped. The map blocks, so it has completed
+// by the time it returns.
+fn map_imported(this: &Buffer, queue: &Queue, range: Range<usize>, mode: MapMode)
+                -> Result<*mut u8, Error> {
+    unsafe {
+        let queue_id = cl_queue::cl_command_queue(queue);
+        let objects = [this.data() as cl_mem];
+        let acquired = try!(cl_queue::acquire_gl_objects(queue_id, &objects, &[]));
+        let result = map_after(this, queue, range, mode, &[acquired]);
+        if result.is_err() {
+            cl_queue::release_gl_objects(queue_id, &objects, acquired)
+        }
+        ffi::clReleaseEvent(acquired);
+        result
+    }
+}
+
+unsafe fn unmap_imported(this: &Buffer, queue: &Queue, ptr: *mut u8) {
+    let queue = cl_queue::cl_command_queue(queue);
+    let mut unmapped = ptr::null_mut();
+    if ffi::clEnqueueUnmapMemObject(queue,
+                                    this.data() as cl_mem,
+                                    ptr as *mut c_void,
+                                    0,
+                                    ptr::null(),
+                                    &mut unmapped) != CL_SUCCESS {
+        unmapped = ptr::null_mut()
+    }
+    cl_queue::release_gl_objects(queue, &[this.data() as cl_mem], unmapped);
+    if !unmapped.is_null() {
+        ffi::clReleaseEvent(unmapped);
+    }
+}
+
+fn copy_to(this: &Buffer, queue: &Queue, external_buffer: &ExternalBuffer) -> Result<(), Error> {
+    unsafe {
+        match *external_buffer {
+            ExternalBuffer::GlBuffer(buffer) => {
+                if gl_buffer::gl_buffer_size(buffer) < this.size() {
+                    return Err(Error::OutOfBounds)
+                }
+
+                let queue = cl_queue::cl_command_queue(queue);
+                let mut context: cl_context = ptr::null_mut();
+                if ffi::clGetCommandQueueInfo(queue,
+                                              CL_QUEUE_CONTEXT,
+                                              mem::size_of::<cl_context>(),
+                                              &mut context as *mut cl_context as *mut c_void,
+                                              ptr::null_mut()) != CL_SUCCESS {
+                    return Err(Error::Failed)
+                }
+
+                let mut error = CL_SUCCESS;
+                let gl_mem = ffi::clCreateFromGLBuffer(context,
+                                                       CL_MEM_WRITE_ONLY,
+                                                       buffer,
+                                                       &mut error);
+                if error != CL_SUCCESS {
+                    return Err(Error::Failed)
+                }
+
+                // This buffer must be acquired too if it was itself imported from OpenGL.
+                let mut objects = vec![gl_mem];
+                if is_imported(this) {
+                    objects.push(this.data() as cl_mem)
+                }
+
+                // The copy waits on the acquire and the release waits on the copy, since the
+                // queue may be out of order.
+                let mut result = Err(Error::Failed);
+                if let Ok(acquired) = cl_queue::acquire_gl_objects(queue, &objects, &[]) {
+                    let mut copied = ptr::null_mut();
+                    if ffi::clEnqueueCopyBuffer(queue,
+                                                this.data() as cl_mem,
+                                                gl_mem,
+                                                0,
+                                                0,
+                                                this.size(),
+                                                1,
+                                                &acquired,
+                                                &mut copied) == CL_SUCCESS {
+                        result = Ok(());
+                        cl_queue::release_gl_objects(queue, &objects, copied);
+                        ffi::clReleaseEvent(copied);
+                    } else {
+                        cl_queue::release_gl_objects(queue, &objects, acquired);
+                    }
+                    ffi::clReleaseEvent(acquired);
+                }
+
+                // OpenGL may use the buffer as soon as this returns.
+                ffi::clFinish(queue);
+                ffi::clReleaseMemObject(gl_mem);
+                result
+            }
+        }
+    }
+}
@@ -8,24 +8,42 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::cl::buffer::BUFFER_FUNCTIONS;
-use api::cl::ffi::{self, CL_CONTEXT_DEVICES, CL_FLOAT, CL_MEM_COPY_HOST_PTR, CL_MEM_READ_ONLY};
+use api::cl::buffer::{BUFFER_FUNCTIONS, HOST_BUFFER_FUNCTIONS};
+use api::cl::buffer::IMPORTED_BUFFER_FUNCTIONS;
+use api::cl::ffi::{self, CL_CONTEXT_DEVICES, CL_MEM_COPY_HOST_PTR, CL_MEM_READ_ONLY};
 use api::cl::ffi::{CL_MEM_READ_WRITE, CL_MEM_WRITE_ONLY, CL_PROGRAM_BUILD_LOG};
-use api::cl::ffi::{CL_QUEUE_PROFILING_ENABLE, CL_R, CL_RGBA, CL_SUCCESS, CL_UNORM_INT8};
-use api::cl::ffi::{cl_context, cl_device_id, cl_image_format, cl_mem_flags};
-use api::cl::image::IMAGE_FUNCTIONS;
-use api::cl::program::PROGRAM_FUNCTIONS;
-use api::cl::queue::QUEUE_FUNCTIONS;
-use buffer::{Buffer, BufferData, Protection};
+use api::cl::ffi::{CL_QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE, CL_QUEUE_PROFILING_ENABLE};
+use api::cl::ffi::{CL_PROFILING_COMMAND_QUEUED, CL_SUCCESS};
+use api::cl::ffi::{cl_command_queue_properties, cl_context};
+use api::cl::ffi::{cl_device_id, cl_image_format, cl_mem_flags, cl_uint};
+use api::cl::ffi::{CL_ADDRESS_CLAMP, CL_ADDRESS_CLAMP_TO_EDGE, CL_ADDRESS_MIRRORED_REPEAT};
+use api::cl::ffi::{CL_ADDRESS_REPEAT, CL_DEVICE_MEM_BASE_ADDR_ALIGN, CL_FALSE, CL_FILTER_LINEAR};
+use api::cl::ffi::{CL_FILTER_NEAREST, CL_MEM_ALLOC_HOST_PTR, CL_MEM_HOST_READ_ONLY};
+use api::cl::ffi::{CL_DEVICE_GLOBAL_MEM_SIZE, CL_MEM_HOST_WRITE_ONLY, CL_MEM_USE_HOST_PTR, CL_TRUE};
+use api::cl::ffi::{CL_MEM_OBJECT_IMAGE2D, cl_ulong};
+use api::cl::image::{self, IMAGE_FUNCTIONS, IMPORTED_IMAGE_FUNCTIONS};
+use api::cl::profile_event;
+use api::cl::program as cl_program;
+use api::cl::queue as cl_queue;
+use api::cl::sampler::SAMPLER_FUNCTIONS;
+use api::cl::sync_event::SYNC_EVENT_FUNCTIONS;
+use api::gl::buffer as gl_buffer;
+use buffer::{Buffer, BufferData, ExternalBuffer, MemoryLocation, Protection};
 use device::{Device, DeviceFunctions};
 use error::Error;
+use gl;
 use euclid::Size2D;
-use image::{Format, Image};
+use image::{ExternalImage, Format, Image};
+use memory::DriverMemoryInfo;
+use profile_event::ClockCalibration;
 use program::Program;
-use queue::Queue;
+use queue::{Queue, QueueOptions};
+use sampler::{AddressMode, Filter, Sampler};
+use sync_event::SyncEvent;
 use std::mem;
-use std::os::raw::c_void;
+use std::os::raw::{c_char, c_void};
 use std::ptr;
+use std::time::Instant;
 
 #[cfg(target_os = "macos")]
 use core_foundation::base::TCFType;
@@ -43,30 +61,40 @@ pub static DEVICE_FUNCTIONS: DeviceFunctions = DeviceFunctions {
     create_queue: create_queue,
     create_program: create_program,
     create_buffer: create_buffer,
+    create_host_buffer: create_host_buffer,
     create_image: create_image,
+    create_user_event: create_user_event,
+    calibrate_clock: calibrate_clock,
+    buffer_offset_alignment: buffer_offset_alignment,
+    create_sampler: create_sampler,
+    driver_memory_info: driver_memory_info,
+    import_buffer: import_buffer,
+    import_image: import_image,
+    supports_image_format: supports_image_format,
 };
 
 unsafe fn destroy(this: &Device) {
     ffi::clReleaseContext(this.data() as cl_context);
 }
 
-fn create_queue(this: &Device) -> Result<Queue, Error> {
+fn create_queue(this: &Device, options: &QueueOptions) -> Result<Queue, Error> {
     unsafe {
-        let mut device_id: cl_device_id = ptr::null_mut();
-        if ffi::clGetContextInfo(this.data() as cl_context,
-                                 CL_CONTEXT_DEVICES,
-                                 mem::size_of::<cl_device_id>(),
-                                 &mut device_id as *mut cl_device_id as *mut c_void,
-                                 ptr::null_mut()) != CL_SUCCESS {
-            return Err(Error::Failed)
+        let device_id = try!(device_id(this));
+
+        let mut properties: cl_command_queue_properties = 0;
+        if options.out_of_order {
+            properties |= CL_QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE
+        }
+        if options.profiling {
+            properties |= CL_QUEUE_PROFILING_ENABLE
         }
 
         let queue = ffi::clCreateCommandQueue(this.data() as cl_context,
                                               device_id,
-                                              CL_QUEUE_PROFILING_ENABLE,
+                                              properties,
                                               ptr::null_mut());
         if queue != ptr::null_mut() {
-            Ok(Queue::from_raw_data(queue as usize, &QUEUE_FUNCTIONS))
+            Ok(cl_queue::create(queue, options.profiling))
         } else {
             Err(Error::Failed)
         }
@@ -86,21 +114,15 @@ fn create_program(this: &Device, source: &str) -> Result<Program, Error> {
             return Err(Error::Failed)
         }
 
-        let mut device_id: cl_device_id = ptr::null_mut();
-        if ffi::clGetContextInfo(this.data() as cl_context,
-                                 CL_CONTEXT_DEVICES,
-                                 mem::size_of::<cl_device_id>(),
-                                 &mut device_id as *mut cl_device_id as *mut c_void,
-                                 ptr::null_mut()) != CL_SUCCESS {
-            return Err(Error::Failed)
-        }
+        let device_id = try!(device_id(this));
 
-        let null = 0;
+        // Keep argument names around so that uniforms can be bound by name.
+        let options = b"-cl-kernel-arg-info\0";
 
         if ffi::clBuildProgram(program,
                                1,
                                &device_id,
-                               &null,
+                               options.as_ptr() as *const c_char,
                                None,
                                ptr::null_mut()) != CL_SUCCESS {
             let mut build_log = vec![0; 65536];
@@ -121,14 +143,23 @@ fn create_program(this: &Device, source: &str) -> Result<Program, Error> {
             return Err(Error::Failed)
         }
 
-        Ok(Program::from_raw_data(kernel as usize, &PROGRAM_FUNCTIONS))
+        cl_program::create(kernel)
     }
 }
 
-fn create_buffer(this: &Device, protection: Protection, mut data: BufferData)
+fn create_buffer(this: &Device,
+                 protection: Protection,
+                 location: MemoryLocation,
+                 mut data: BufferData)
                  -> Result<Buffer, Error> {
     unsafe {
         let mut mem_flags = protection_to_mem_flags(protection);
+        mem_flags |= match location {
+            MemoryLocation::DeviceLocal => 0,
+            MemoryLocation::Upload => CL_MEM_ALLOC_HOST_PTR | CL_MEM_HOST_WRITE_ONLY,
+            MemoryLocation::Readback => CL_MEM_ALLOC_HOST_PTR | CL_MEM_HOST_READ_ONLY,
+            MemoryLocation::Streaming | MemoryLocation::Host => CL_MEM_ALLOC_HOST_PTR,
+        };
         let (size, host_ptr);
         match data {
             BufferData::HostAllocated(ref mut buffer) => {
@@ -149,54 +180,53 @@ fn create_buffer(this: &Device, protection: Protection, mut data: BufferData)
                                          host_ptr as *mut c_void,
                                          ptr::null_mut());
         if !buffer.is_null() {
-            Ok(Buffer::from_raw_data(buffer as usize, &BUFFER_FUNCTIONS))
+            Ok(Buffer::from_raw_data(buffer as usize,
+                                     size,
+                                     protection,
+                                     location,
+                                     &BUFFER_FUNCTIONS))
         } else {
             Err(Error::Failed)
         }
     }
 }
 
+unsafe fn create_host_buffer(this: &Device, protection: Protection, data: *mut u8, size: usize)
+                             -> Result<Buffer, Error> {
+    let mem_flags = protection_to_mem_flags(protection) | CL_MEM_USE_HOST_PTR;
+    let buffer = ffi::clCreateBuffer(this.data() as cl_context,
+                                     mem_flags,
+                                     size,
+                                     data as *mut c_void,
+                                     ptr::null_mut());
+    if !buffer.is_null() {
+        Ok(Buffer::from_raw_data(buffer as usize,
+                                 size,
+                                 protection,
+                                 MemoryLocation::Host,
+                                 &HOST_BUFFER_FUNCTIONS))
+    } else {
+        Err(Error::Failed)
+    }
+}
+
 #[cfg(target_os = "macos")]
 fn create_image(this: &Device, format: Format, protection: Protection, size: &Size2D<u32>)
                 -> Result<Image, Error> {
     unsafe {
-        let bytes_per_element = match format {
-            Format::R8 => 1,
-            Format::RGBA8 | Format::R32F => 4,
-        };
-
         let properties = CFDictionary::from_CFType_pairs(&[
             (CFString::wrap_under_get_rule(kIOSurfaceWidth),
              CFNumber::from_i64(size.width as i64)),
             (CFString::wrap_under_get_rule(kIOSurfaceHeight),
              CFNumber::from_i64(size.height as i64)),
             (CFString::wrap_under_get_rule(kIOSurfaceBytesPerElement),
-             CFNumber::from_i32(bytes_per_element)),
+             CFNumber::from_i32(format.bytes_per_pixel() as i32)),
         ]);
         let surface = io_surface::new(&properties);
 
         let protection = protection_to_mem_flags(protection);
 
-        let image_format = match format {
-            Format::R8 => {
-                cl_image_format {
-                    image_channel_order: CL_R,
-                    image_channel_data_type: CL_UNORM_INT8,
-                }
-            }
-            Format::RGBA8 => {
-                cl_image_format {
-                    image_channel_order: CL_RGBA,
-                    image_channel_data_type: CL_UNORM_INT8,
-                }
-            }
-            Format::R32F => {
-                cl_image_format {
-                    image_channel_order: CL_R,
-                    image_channel_data_type: CL_FLOAT,
-                }
-            }
-        };
+        let image_format = image::cl_image_format(format);
 
         let mut error = CL_SUCCESS;
 
@@ -219,9 +249,220 @@ fn create_image(this: &Device, format: Format, protection: Protection, size: &Si
     }
 }
 
+fn create_user_event(this: &Device) -> Result<SyncEvent, Error> {
+    unsafe {
+        let mut error = CL_SUCCESS;
+        let event = ffi::clCreateUserEvent(this.data() as cl_context, &mut error);
+        if error != CL_SUCCESS || event.is_null() {
+            return Err(Error::Failed)
+        }
+
+        Ok(SyncEvent::from_raw_data(event as usize, &SYNC_EVENT_FUNCTIONS))
+    }
+}
+
+// OpenCL 1.2 has no way to read the device clock directly, but the "queued" timestamp of a
+// command is sampled on the host when the command is enqueued. So enqueue a marker, take the host
+// time on either side of that, and use the midpoint.
+fn calibrate_clock(this: &Device) -> Result<ClockCalibration, Error> {
+    unsafe {
+        // The queued time is only recorded on profiling queues, which are not the default.
+        let queue = try!(create_queue(this, &QueueOptions {
+            profiling: true,
+            ..QueueOptions::default()
+        }));
+
+        let mut event = ptr::null_mut();
+        let before = Instant::now();
+        let result = ffi::clEnqueueMarker(cl_queue::cl_command_queue(&queue), &mut event);
+        let after = Instant::now();
+        if result != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+
+        let gpu_timestamp = if ffi::clWaitForEvents(1, &event) == CL_SUCCESS {
+            profile_event::profiling_info(event, CL_PROFILING_COMMAND_QUEUED)
+        } else {
+            Err(Error::Failed)
+        };
+        ffi::clReleaseEvent(event);
+
+        Ok(ClockCalibration {
+            gpu_timestamp: try!(gpu_timestamp),
+            host_time: before + (after - before) / 2,
+        })
+    }
+}
+
+fn buffer_offset_alignment(this: &Device) -> Result<usize, Error> {
+    unsafe {
+        let device_id = try!(device_id(this));
+
+        // OpenCL reports this in bits.
+        let mut alignment: cl_uint = 0;
+        if ffi::clGetDeviceInfo(device_id,
+                                CL_DEVICE_MEM_BASE_ADDR_ALIGN,
+                                mem::size_of::<cl_uint>(),
+                                &mut alignment as *mut cl_uint as *mut c_void,
+                                ptr::null_mut()) != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+
+        Ok(alignment as usize / 8)
+    }
+}
+
+fn create_sampler(this: &Device,
+                  filter: Filter,
+                  address_mode: AddressMode,
+                  normalized_coords: bool)
+                  -> Result<Sampler, Error> {
+    let address_mode = match address_mode {
+        AddressMode::ClampToEdge => CL_ADDRESS_CLAMP_TO_EDGE,
+        AddressMode::ClampToBorder => CL_ADDRESS_CLAMP,
+        AddressMode::Repeat => CL_ADDRESS_REPEAT,
+        AddressMode::MirroredRepeat => CL_ADDRESS_MIRRORED_REPEAT,
+    };
+    let filter = match filter {
+        Filter::Nearest => CL_FILTER_NEAREST,
+        Filter::Linear => CL_FILTER_LINEAR,
+    };
+
+    unsafe {
+        let mut error = CL_SUCCESS;
+        let sampler = ffi::clCreateSampler(this.data() as cl_context,
+                                           if normalized_coords { CL_TRUE } else { CL_FALSE },
+                                           address_mode,
+                                           filter,
+                                           &mut error);
+        if error == CL_SUCCESS && !sampler.is_null() {
+            Ok(Sampler::from_raw_data(sampler as usize, &SAMPLER_FUNCTIONS))
+        } else {
+            Err(Error::Failed)
+        }
+    }
+}
+
+// OpenCL reports the size of global memory but not how much of it is free.
+fn driver_memory_info(this: &Device) -> Result<Option<DriverMemoryInfo>, Error> {
+    unsafe {
+        let device_id = try!(device_id(this));
+
+        let mut global_mem_size: cl_ulong = 0;
+        if ffi::clGetDeviceInfo(device_id,
+                                CL_DEVICE_GLOBAL_MEM_SIZE,
+                                mem::size_of::<cl_ulong>(),
+                                &mut global_mem_size as *mut cl_ulong as *mut c_void,
+                                ptr::null_mut()) != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+
+        Ok(Some(DriverMemoryInfo {
+            total_bytes: Some(global_mem_size),
+            available_bytes: None,
+        }))
+    }
+}
+
+fn import_buffer(this: &Device, external_buffer: &ExternalBuffer, protection: Protection)
+                 -> Result<Buffer, Error> {
+    unsafe {
+        match *external_buffer {
+            ExternalBuffer::GlBuffer(buffer) => {
+                let size = gl_buffer::gl_buffer_size(buffer);
+
+                let mut error = CL_SUCCESS;
+                let mem = ffi::clCreateFromGLBuffer(this.data() as cl_context,
+                                                    protection_to_mem_flags(protection),
+                                                    buffer,
+                                                    &mut error);
+                if error != CL_SUCCESS || mem.is_null() {
+                    return Err(Error::Failed)
+                }
+
+                Ok(Buffer::from_raw_data(mem as usize,
+                                         size,
+                                         protection,
+                                         MemoryLocation::DeviceLocal,
+                                         &IMPORTED_BUFFER_FUNCTIONS))
+            }
+        }
+    }
+}
+
+fn import_image(this: &Device, external_image: &ExternalImage, protection: Protection)
+                -> Result<Image, Error> {
+    unsafe {
+        match *external_image {
+            ExternalImage::GlTexture(texture) => {
+                let mut error = CL_SUCCESS;
+                let mem = ffi::clCreateFromGLTexture(this.data() as cl_context,
+                                                     protection_to_mem_flags(protection),
+                                                     gl::TEXTURE_RECTANGLE,
+                                                     0,
+                                                     texture,
+                                                     &mut error);
+                if error != CL_SUCCESS || mem.is_null() {
+                    return Err(Error::Failed)
+                }
+
+                let image = Image::from_raw_data([mem as usize, 0], &IMPORTED_IMAGE_FUNCTIONS);
+                if image.format().is_err() {
+                    return Err(Error::Unsupported("importing textures of this format"))
+                }
+                Ok(image)
+            }
+        }
+    }
+}
+
+fn supports_image_format(this: &Device, format: Format, protection: Protection)
+                         -> Result<bool, Error> {
+    unsafe {
+        let flags = protection_to_mem_flags(protection);
+        let mut num_formats = 0;
+        if ffi::clGetSupportedImageFormats(this.data() as cl_context,
+                                           flags,
+                                           CL_MEM_OBJECT_IMAGE2D,
+                                           0,
+                                           ptr::null_mut(),
+                                           &mut num_formats) != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+
+        let mut formats = vec![cl_image_format::default(); num_formats as usize];
+        if ffi::clGetSupportedImageFormats(this.data() as cl_context,
+                                           flags,
+                                           CL_MEM_OBJECT_IMAGE2D,
+                                           num_formats,
+                                           formats.as_mut_ptr(),
+                                           ptr::null_mut()) != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+
+        let wanted = image::cl_image_format(format);
+        Ok(formats.iter().any(|format| {
+            format.image_channel_order == wanted.image_channel_order &&
+                format.image_channel_data_type == wanted.image_channel_data_type
+        }))
+    }
+}
+
+unsafe fn device_id(this: &Device) -> Result<cl_device_id, Error> {
+    let mut device_id: cl_device_id = ptr::null_mut();
+    if ffi::clGetContextInfo(this.data() as cl_context,
+                             CL_CONTEXT_DEVICES,
+                             mem::size_of::<cl_device_id>(),
+                             &mut device_id as *mut cl_device_id as *mut c_void,
+                             ptr::null_mut()) != CL_SUCCESS {
+        return Err(Error::Failed)
+    }
+    Ok(device_id)
+}
+
 fn protection_to_mem_flags(protection: Protection) -> cl_mem_flags {
     match protection {
-        Protection::ReadOnly => CL_MEM_READ_ONLY,
+        Protection::ReadOnly | Protection::Constant => CL_MEM_READ_ONLY,
         Protection::WriteOnly => CL_MEM_WRITE_ONLY,
         Protection::ReadWrite => CL_MEM_READ_WRITE,
     }
@@ -30,6 +30,8 @@ pub enum _cl_kernel { }
 pub type cl_kernel = *mut _cl_kernel;
 pub enum _cl_event { }
 pub type cl_event = *mut _cl_event;
+pub enum _cl_sampler { }
+pub type cl_sampler = *mut _cl_sampler;
 pub type cl_bool = cl_uint;
 pub type cl_bitfield = cl_ulong;
 pub type cl_device_type = cl_bitfield;
@@ -39,11 +41,27 @@ pub type cl_context_info = cl_uint;
 pub type cl_program_build_info = cl_uint;
 pub type cl_device_info = cl_uint;
 pub type cl_kernel_info = cl_uint;
+pub type cl_kernel_arg_info = cl_uint;
+pub type cl_kernel_arg_address_qualifier = cl_uint;
 pub type cl_channel_order = cl_uint;
 pub type cl_channel_type = cl_uint;
 pub type cl_mem_flags = cl_bitfield;
 pub type cl_image_info = cl_uint;
 pub type cl_profiling_info = cl_uint;
+pub type cl_event_info = cl_uint;
+pub type cl_command_queue_info = cl_uint;
+pub type cl_buffer_create_type = cl_uint;
+pub type cl_addressing_mode = cl_uint;
+pub type cl_map_flags = cl_bitfield;
+pub type cl_filter_mode = cl_uint;
+pub type cl_mem_object_type = cl_uint;
+pub type cl_GLuint = uint32_t;
+pub type cl_GLint = int32_t;
+pub type cl_GLenum = uint32_t;
+pub enum _CGLContextObject { }
+pub type CGLContextObj = *mut _CGLContextObject;
+pub enum _CGLShareGroupObject { }
+pub type CGLShareGroupObj = *mut _CGLShareGroupObject;
 
 #[repr(C)]
 #[derive(Copy, Clone)]
@@ -57,6 +75,15 @@ impl ::std::default::Default for _cl_image_format {
 }
 pub type cl_image_format = _cl_image_format;
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+#[derive(Debug)]
+pub struct _cl_buffer_region {
+    pub origin: size_t,
+    pub size: size_t,
+}
+pub type cl_buffer_region = _cl_buffer_region;
+
 #[link(name = "OpenCL", kind = "framework")]
 extern "C" {
     pub fn clGetDeviceIDs(arg1: cl_platform_id, arg2: cl_device_type,
@@ -86,10 +113,55 @@ extern "C" {
                                 arg3: cl_command_queue_properties,
                                 arg4: *mut cl_int) -> cl_command_queue;
     pub fn clReleaseCommandQueue(arg1: cl_command_queue) -> cl_int;
+    pub fn clGetCommandQueueInfo(command_queue: cl_command_queue,
+                                 param_name: cl_command_queue_info,
+                                 param_value_size: size_t,
+                                 param_value: *mut ::std::os::raw::c_void,
+                                 param_value_size_ret: *mut size_t)
+                                 -> cl_int;
     pub fn clCreateBuffer(arg1: cl_context, arg2: cl_mem_flags, arg3: size_t,
                           arg4: *mut ::std::os::raw::c_void,
                           arg5: *mut cl_int) -> cl_mem;
+    pub fn clCreateSubBuffer(buffer: cl_mem,
+                             flags: cl_mem_flags,
+                             buffer_create_type: cl_buffer_create_type,
+                             buffer_create_info: *const ::std::os::raw::c_void,
+                             errcode_ret: *mut cl_int)
+                             -> cl_mem;
+    pub fn clCreateFromGLBuffer(context: cl_context,
+                                flags: cl_mem_flags,
+                                bufobj: cl_GLuint,
+                                errcode_ret: *mut cl_int)
+                                -> cl_mem;
+    pub fn clCreateFromGLTexture(context: cl_context,
+                                 flags: cl_mem_flags,
+                                 target: cl_GLenum,
+                                 miplevel: cl_GLint,
+                                 texture: cl_GLuint,
+                                 errcode_ret: *mut cl_int)
+                                 -> cl_mem;
+    pub fn clGetSupportedImageFormats(context: cl_context,
+                                      flags: cl_mem_flags,
+                                      image_type: cl_mem_object_type,
+                                      num_entries: cl_uint,
+                                      image_formats: *mut cl_image_format,
+                                      num_image_formats: *mut cl_uint)
+                                      -> cl_int;
     pub fn clReleaseMemObject(arg1: cl_mem) -> cl_int;
+    pub fn clSetMemObjectDestructorCallback(memobj: cl_mem,
+                                            pfn_notify:
+                                                ::std::option::Option<unsafe extern "C" fn(
+                                                    memobj: cl_mem,
+                                                    user_data: *mut ::std::os::raw::c_void)>,
+                                            user_data: *mut ::std::os::raw::c_void)
+                                            -> cl_int;
+    pub fn clCreateSampler(context: cl_context,
+                           normalized_coords: cl_bool,
+                           addressing_mode: cl_addressing_mode,
+                           filter_mode: cl_filter_mode,
+                           errcode_ret: *mut cl_int)
+                           -> cl_sampler;
+    pub fn clReleaseSampler(sampler: cl_sampler) -> cl_int;
     pub fn clGetImageInfo(arg1: cl_mem, arg2: cl_image_info, arg3: size_t,
                           arg4: *mut ::std::os::raw::c_void,
                           arg5: *mut size_t) -> cl_int;
@@ -126,7 +198,30 @@ extern "C" {
                            param_value: *mut ::std::os::raw::c_void,
                            param_value_size_ret: *mut size_t)
                            -> cl_int;
+    pub fn clGetKernelArgInfo(kernel: cl_kernel,
+                              arg_indx: cl_uint,
+                              param_name: cl_kernel_arg_info,
+                              param_value_size: size_t,
+                              param_value: *mut ::std::os::raw::c_void,
+                              param_value_size_ret: *mut size_t)
+                              -> cl_int;
     pub fn clWaitForEvents(arg1: cl_uint, arg2: *const cl_event) -> cl_int;
+    pub fn clCreateUserEvent(context: cl_context, errcode_ret: *mut cl_int) -> cl_event;
+    pub fn clSetUserEventStatus(event: cl_event, execution_status: cl_int) -> cl_int;
+    pub fn clGetEventInfo(event: cl_event,
+                          param_name: cl_event_info,
+                          param_value_size: size_t,
+                          param_value: *mut ::std::os::raw::c_void,
+                          param_value_size_ret: *mut size_t)
+                          -> cl_int;
+    pub fn clSetEventCallback(event: cl_event,
+                              command_exec_callback_type: cl_int,
+                              pfn_notify:
+                                  ::std::option::Option<unsafe extern "C" fn(event: cl_event,
+                                                                             event_command_exec_status: cl_int,
+                                                                             user_data: *mut ::std::os::raw::c_void)>,
+                              user_data: *mut ::std::os::raw::c_void)
+                              -> cl_int;
     pub fn clReleaseEvent(arg1: cl_event) -> cl_int;
     pub fn clGetEventProfilingInfo(arg1: cl_event, arg2: cl_profiling_info,
                                    arg3: size_t,
@@ -139,6 +234,34 @@ extern "C" {
                                arg6: *mut ::std::os::raw::c_void,
                                arg7: cl_uint, arg8: *const cl_event,
                                arg9: *mut cl_event) -> cl_int;
+    pub fn clEnqueueCopyBuffer(command_queue: cl_command_queue,
+                               src_buffer: cl_mem,
+                               dst_buffer: cl_mem,
+                               src_offset: size_t,
+                               dst_offset: size_t,
+                               size: size_t,
+                               num_events_in_wait_list: cl_uint,
+                               event_wait_list: *const cl_event,
+                               event: *mut cl_event)
+                               -> cl_int;
+    pub fn clEnqueueMapBuffer(command_queue: cl_command_queue,
+                              buffer: cl_mem,
+                              blocking_map: cl_bool,
+                              map_flags: cl_map_flags,
+                              offset: size_t,
+                              size: size_t,
+                              num_events_in_wait_list: cl_uint,
+                              event_wait_list: *const cl_event,
+                              event: *mut cl_event,
+                              errcode_ret: *mut cl_int)
+                              -> *mut ::std::os::raw::c_void;
+    pub fn clEnqueueUnmapMemObject(command_queue: cl_command_queue,
+                                   memobj: cl_mem,
+                                   mapped_ptr: *mut ::std::os::raw::c_void,
+                                   num_events_in_wait_list: cl_uint,
+                                   event_wait_list: *const cl_event,
+                                   event: *mut cl_event)
+                                   -> cl_int;
     pub fn clEnqueueFillImage(arg1: cl_command_queue, arg2: cl_mem,
                               arg3: *const ::std::os::raw::c_void,
                               arg4: *const size_t, arg5: *const size_t,
@@ -150,6 +273,21 @@ extern "C" {
                                   arg7: cl_uint, arg8: *const cl_event,
                                   arg9: *mut cl_event) -> cl_int;
     pub fn clEnqueueMarker(arg1: cl_command_queue, arg2: *mut cl_event) -> cl_int;
+    pub fn clEnqueueBarrier(arg1: cl_command_queue) -> cl_int;
+    pub fn clEnqueueAcquireGLObjects(command_queue: cl_command_queue,
+                                     num_objects: cl_uint,
+                                     mem_objects: *const cl_mem,
+                                     num_events_in_wait_list: cl_uint,
+                                     event_wait_list: *const cl_event,
+                                     event: *mut cl_event)
+                                     -> cl_int;
+    pub fn clEnqueueReleaseGLObjects(command_queue: cl_command_queue,
+                                     num_objects: cl_uint,
+                                     mem_objects: *const cl_mem,
+                                     num_events_in_wait_list: cl_uint,
+                                     event_wait_list: *const cl_event,
+                                     event: *mut cl_event)
+                                     -> cl_int;
 
     #[cfg(target_os = "macos")]
     pub fn clCreateImageFromIOSurface2DAPPLE(context: cl_context,
@@ -162,38 +300,99 @@ extern "C" {
                                              -> cl_mem;
 }
 
+#[link(name = "OpenGL", kind = "framework")]
+extern "C" {
+    pub fn CGLGetCurrentContext() -> CGLContextObj;
+    pub fn CGLGetShareGroup(ctx: CGLContextObj) -> CGLShareGroupObj;
+}
+
 pub const CL_SUCCESS: cl_int = 0;
+pub const CL_MISALIGNED_SUB_BUFFER_OFFSET: cl_int = -13;
 
+pub const CL_FALSE: cl_bool = 0;
 pub const CL_TRUE: cl_bool = 1;
 
 pub const CL_DEVICE_TYPE_GPU: cl_device_type = 1 << 2;
 
+pub const CL_QUEUE_OUT_OF_ORDER_EXEC_MODE_ENABLE: cl_command_queue_properties = 1 << 0;
 pub const CL_QUEUE_PROFILING_ENABLE: cl_command_queue_properties = 1 << 1;
 
+pub const CL_QUEUE_CONTEXT: cl_command_queue_info = 0x1090;
+
+pub const CL_DEVICE_MEM_BASE_ADDR_ALIGN: cl_device_info = 0x1019;
+pub const CL_DEVICE_GLOBAL_MEM_SIZE: cl_device_info = 0x101f;
 pub const CL_DEVICE_NAME: cl_device_info = 0x102b;
 
 pub const CL_CONTEXT_DEVICES: cl_context_info = 0x1081;
 
+pub const CL_CONTEXT_PROPERTY_USE_CGL_SHAREGROUP_APPLE: cl_context_properties = 0x10000000;
+
 pub const CL_R: cl_channel_order = 0x10b0;
+pub const CL_RG: cl_channel_order = 0x10b2;
 pub const CL_RGBA: cl_channel_order = 0x10b5;
+pub const CL_SRGBA: cl_channel_order = 0x10c1; // `CL_sRGBA`
 
 pub const CL_UNORM_INT8: cl_channel_type = 0x10d2;
+pub const CL_UNORM_INT16: cl_channel_type = 0x10d3;
+pub const CL_SIGNED_INT32: cl_channel_type = 0x10d9;
+pub const CL_UNSIGNED_INT8: cl_channel_type = 0x10da;
+pub const CL_UNSIGNED_INT32: cl_channel_type = 0x10dc;
+pub const CL_HALF_FLOAT: cl_channel_type = 0x10dd;
 pub const CL_FLOAT: cl_channel_type = 0x10de;
 
+pub const CL_MEM_OBJECT_IMAGE2D: cl_mem_object_type = 0x10f1;
+
 pub const CL_MEM_READ_WRITE: cl_mem_flags = 1 << 0;
 pub const CL_MEM_WRITE_ONLY: cl_mem_flags = 1 << 1;
 pub const CL_MEM_READ_ONLY: cl_mem_flags = 1 << 2;
+pub const CL_MEM_USE_HOST_PTR: cl_mem_flags = 1 << 3;
+pub const CL_MEM_ALLOC_HOST_PTR: cl_mem_flags = 1 << 4;
 pub const CL_MEM_COPY_HOST_PTR: cl_mem_flags = 1 << 5;
+pub const CL_MEM_HOST_WRITE_ONLY: cl_mem_flags = 1 << 7;
+pub const CL_MEM_HOST_READ_ONLY: cl_mem_flags = 1 << 8;
+
+pub const CL_MAP_READ: cl_map_flags = 1 << 0;
+pub const CL_MAP_WRITE: cl_map_flags = 1 << 1;
+pub const CL_MAP_WRITE_INVALIDATE_REGION: cl_map_flags = 1 << 2;
+
+pub const CL_BUFFER_CREATE_TYPE_REGION: cl_buffer_create_type = 0x1220;
 
 pub const CL_IMAGE_FORMAT: cl_image_info = 0x1110;
 pub const CL_IMAGE_WIDTH: cl_image_info = 0x1114;
 pub const CL_IMAGE_HEIGHT: cl_image_info = 0x1115;
 pub const CL_IMAGE_DEPTH: cl_image_info = 0x1116;
 
+pub const CL_ADDRESS_CLAMP_TO_EDGE: cl_addressing_mode = 0x1131;
+pub const CL_ADDRESS_CLAMP: cl_addressing_mode = 0x1132;
+pub const CL_ADDRESS_REPEAT: cl_addressing_mode = 0x1133;
+pub const CL_ADDRESS_MIRRORED_REPEAT: cl_addressing_mode = 0x1134;
+
+pub const CL_FILTER_NEAREST: cl_filter_mode = 0x1140;
+pub const CL_FILTER_LINEAR: cl_filter_mode = 0x1141;
+
 pub const CL_PROGRAM_BUILD_LOG: cl_program_build_info = 0x1183;
 
+pub const CL_KERNEL_NUM_ARGS: cl_kernel_info = 0x1191;
 pub const CL_KERNEL_PROGRAM: cl_kernel_info = 0x1194;
 
+pub const CL_KERNEL_ARG_ADDRESS_QUALIFIER: cl_kernel_arg_info = 0x1196;
+pub const CL_KERNEL_ARG_TYPE_NAME: cl_kernel_arg_info = 0x1198;
+pub const CL_KERNEL_ARG_NAME: cl_kernel_arg_info = 0x119a;
+
+pub const CL_KERNEL_ARG_ADDRESS_GLOBAL: cl_kernel_arg_address_qualifier = 0x119b;
+pub const CL_KERNEL_ARG_ADDRESS_LOCAL: cl_kernel_arg_address_qualifier = 0x119c;
+pub const CL_KERNEL_ARG_ADDRESS_CONSTANT: cl_kernel_arg_address_qualifier = 0x119d;
+
+pub const CL_EVENT_COMMAND_QUEUE: cl_event_info = 0x11d0;
+pub const CL_EVENT_COMMAND_EXECUTION_STATUS: cl_event_info = 0x11d3;
+
+pub const CL_COMPLETE: cl_int = 0x0;
+pub const CL_RUNNING: cl_int = 0x1;
+pub const CL_SUBMITTED: cl_int = 0x2;
+pub const CL_QUEUED: cl_int = 0x3;
+
+pub const CL_PROFILING_COMMAND_QUEUED: cl_profiling_info = 0x1280;
+pub const CL_PROFILING_COMMAND_SUBMIT: cl_profiling_info = 0x1281;
 pub const CL_PROFILING_COMMAND_START: cl_profiling_info = 0x1282;
 pub const CL_PROFILING_COMMAND_END: cl_profiling_info = 0x1283;
 
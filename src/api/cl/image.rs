@@ -8,8 +8,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::cl::ffi::{self, CL_FLOAT, CL_IMAGE_FORMAT, CL_IMAGE_HEIGHT, CL_IMAGE_WIDTH, CL_R};
-use api::cl::ffi::{CL_RGBA, CL_SUCCESS, CL_UNORM_INT8, cl_image_format, cl_mem};
+use api::cl::ffi::{self, CL_FLOAT, CL_HALF_FLOAT, CL_IMAGE_FORMAT, CL_IMAGE_HEIGHT};
+use api::cl::ffi::{CL_IMAGE_WIDTH, CL_R, CL_RG, CL_RGBA, CL_SIGNED_INT32, CL_SUCCESS};
+use api::cl::ffi::{CL_UNORM_INT16, CL_UNORM_INT8, CL_UNSIGNED_INT32, CL_UNSIGNED_INT8, CL_SRGBA};
+use api::cl::ffi::{cl_image_format, cl_mem};
 use error::Error;
 use gl;
 use image::{ExternalImage, Format, Image, ImageFunctions};
@@ -30,6 +32,20 @@ pub static IMAGE_FUNCTIONS: ImageFunctions = ImageFunctions {
     format: format,
 };
 
+// Images created from OpenGL textures owned by the caller.
+pub static IMPORTED_IMAGE_FUNCTIONS: ImageFunctions = ImageFunctions {
+    destroy: destroy_imported,
+    bind_to: bind_to_imported,
+    width: width,
+    height: height,
+    format: format,
+};
+
+/// Returns true if `image` was created from an OpenGL texture.
+pub fn is_imported(image: &Image) -> bool {
+    ptr::eq(image.functions(), &IMPORTED_IMAGE_FUNCTIONS)
+}
+
 #[cfg(target_os = "macos")]
 unsafe fn destroy(this: &Image) {
     // Release the `IOSurfaceRef` by wrapping it with no reference count change and letting that
@@ -65,6 +81,14 @@ fn bind_to(this: &Image, external_image: &ExternalImage) -> Result<(), Error> {
     }
 }
 
+unsafe fn destroy_imported(this: &Image) {
+    ffi::clReleaseMemObject(this.data()[0] as cl_mem);
+}
+
+fn bind_to_imported(_: &Image, _: &ExternalImage) -> Result<(), Error> {
+    Err(Error::Unsupported("binding imported images"))
+}
+
 fn width(this: &Image) -> Result<u32, Error> {
     unsafe {
         let mut width = 0usize;
@@ -109,12 +133,44 @@ fn format(this: &Image) -> Result<Format, Error> {
             return Err(Error::Failed)
         }
 
+        // This must match the definition of `cl_image_format()`.
         match (image_format.image_channel_order, image_format.image_channel_data_type) {
             (CL_R, CL_UNORM_INT8) => Ok(Format::R8),
-            (CL_R, CL_FLOAT) => Ok(Format::R32F),
+            (CL_RG, CL_UNORM_INT8) => Ok(Format::RG8),
             (CL_RGBA, CL_UNORM_INT8) => Ok(Format::RGBA8),
-            _ => unreachable!(),
+            (CL_SRGBA, CL_UNORM_INT8) => Ok(Format::SRGBA8),
+            (CL_RGBA, CL_UNSIGNED_INT8) => Ok(Format::RGBA8UI),
+            (CL_R, CL_UNORM_INT16) => Ok(Format::R16),
+            (CL_RG, CL_HALF_FLOAT) => Ok(Format::RG16F),
+            (CL_RGBA, CL_HALF_FLOAT) => Ok(Format::RGBA16F),
+            (CL_R, CL_FLOAT) => Ok(Format::R32F),
+            (CL_RGBA, CL_FLOAT) => Ok(Format::RGBA32F),
+            (CL_R, CL_UNSIGNED_INT32) => Ok(Format::R32UI),
+            (CL_R, CL_SIGNED_INT32) => Ok(Format::R32I),
+            _ => Err(Error::Failed),
         }
     }
 }
 
+// Returns the OpenCL image format corresponding to the given image format.
+pub fn cl_image_format(format: Format) -> cl_image_format {
+    let (order, data_type) = match format {
+        Format::R8 => (CL_R, CL_UNORM_INT8),
+        Format::RG8 => (CL_RG, CL_UNORM_INT8),
+        Format::RGBA8 => (CL_RGBA, CL_UNORM_INT8),
+        Format::SRGBA8 => (CL_SRGBA, CL_UNORM_INT8),
+        Format::RGBA8UI => (CL_RGBA, CL_UNSIGNED_INT8),
+        Format::R16 => (CL_R, CL_UNORM_INT16),
+        Format::RG16F => (CL_RG, CL_HALF_FLOAT),
+        Format::RGBA16F => (CL_RGBA, CL_HALF_FLOAT),
+        Format::R32F => (CL_R, CL_FLOAT),
+        Format::RGBA32F => (CL_RGBA, CL_FLOAT),
+        Format::R32UI => (CL_R, CL_UNSIGNED_INT32),
+        Format::R32I => (CL_R, CL_SIGNED_INT32),
+    };
+    cl_image_format {
+        image_channel_order: order,
+        image_channel_data_type: data_type,
+    }
+}
+
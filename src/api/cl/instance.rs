@@ -9,7 +9,8 @@
 // except according to those terms.
 
 use api::cl::device::DEVICE_FUNCTIONS;
-use api::cl::ffi::{self, CL_DEVICE_TYPE_GPU, CL_DEVICE_NAME, CL_SUCCESS, cl_device_id};
+use api::cl::ffi::{self, CL_CONTEXT_PROPERTY_USE_CGL_SHAREGROUP_APPLE, CL_DEVICE_TYPE_GPU};
+use api::cl::ffi::{CL_DEVICE_NAME, CL_SUCCESS, cl_context_properties, cl_device_id};
 use device::Device;
 use error::Error;
 use gl;
@@ -24,6 +25,7 @@ pub static INSTANCE_FUNCTIONS: InstanceFunctions = InstanceFunctions {
     destroy: destroy,
     shading_language: shading_language,
     open_device: open_device,
+    open_device_with_gl_sharing: open_device_with_gl_sharing,
 };
 
 pub fn create() -> Result<Instance, Error> {
@@ -39,6 +41,14 @@ fn shading_language(_: &Instance) -> ShadingLanguage {
 }
 
 fn open_device(_: &Instance) -> Result<Device, Error> {
+    open(false)
+}
+
+fn open_device_with_gl_sharing(_: &Instance) -> Result<Device, Error> {
+    open(true)
+}
+
+fn open(share_with_gl: bool) -> Result<Device, Error> {
     unsafe {
         let mut num_devices = 0;
         if ffi::clGetDeviceIDs(ptr::null_mut(),
@@ -98,7 +108,23 @@ fn open_device(_: &Instance) -> Result<Device, Error> {
         }
 
         let device_id = best_device_id.unwrap().1;
-        let context = ffi::clCreateContext(ptr::null_mut(),
+
+        // Share objects with the current OpenGL context if asked to, so that buffers and images
+        // can be exchanged with it.
+        let mut properties = [0; 3];
+        if share_with_gl {
+            let share_group = ffi::CGLGetShareGroup(ffi::CGLGetCurrentContext());
+            if share_group.is_null() {
+                return Err(Error::Failed)
+            }
+            properties = [
+                CL_CONTEXT_PROPERTY_USE_CGL_SHAREGROUP_APPLE,
+                share_group as cl_context_properties,
+                0,
+            ];
+        }
+
+        let context = ffi::clCreateContext(properties.as_ptr(),
                                            1,
                                            &device_id,
                                            None,
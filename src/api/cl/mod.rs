@@ -15,6 +15,7 @@ pub mod instance;
 pub mod profile_event;
 pub mod program;
 pub mod queue;
+pub mod sampler;
 pub mod sync_event;
 
 mod ffi;
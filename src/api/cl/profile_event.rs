@@ -8,48 +8,77 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::cl::ffi::{self, CL_PROFILING_COMMAND_END, CL_PROFILING_COMMAND_START, CL_SUCCESS};
-use api::cl::ffi::{cl_event, cl_ulong};
+use api::cl::ffi::{self, CL_PROFILING_COMMAND_END, CL_PROFILING_COMMAND_QUEUED};
+use api::cl::ffi::{CL_PROFILING_COMMAND_START, CL_PROFILING_COMMAND_SUBMIT, CL_SUCCESS};
+use api::cl::ffi::{cl_event, cl_profiling_info, cl_ulong};
+use api::cl::sync_event;
 use error::Error;
-use profile_event::{ProfileEvent, ProfileEventFunctions};
+use profile_event::{ProfileEvent, ProfileEventFunctions, Timestamps};
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
+use std::task::{Poll, Waker};
 
 pub static PROFILE_EVENT_FUNCTIONS: ProfileEventFunctions = ProfileEventFunctions {
     destroy: destroy,
     time_elapsed: time_elapsed,
+    timestamps: timestamps,
+    poll_time_elapsed: poll_time_elapsed,
+    cancel_poll_time_elapsed: cancel_poll_time_elapsed,
 };
 
 unsafe fn destroy(this: &ProfileEvent) {
+    sync_event::forget_event(this.data() as cl_event);
     ffi::clReleaseEvent(this.data() as cl_event);
 }
 
 fn time_elapsed(this: &ProfileEvent) -> Result<u64, Error> {
+    let timestamps = try!(timestamps(this));
+    Ok(timestamps.execution_time())
+}
+
+fn timestamps(this: &ProfileEvent) -> Result<Timestamps, Error> {
     unsafe {
         if ffi::clWaitForEvents(1, &this.data() as *const usize as *const cl_event) != CL_SUCCESS {
             return Err(Error::Failed)
         }
 
-        let mut start_time = 0;
-        if ffi::clGetEventProfilingInfo(this.data() as cl_event,
-                                        CL_PROFILING_COMMAND_START,
-                                        mem::size_of::<cl_ulong>(),
-                                        &mut start_time as *mut u64 as *mut c_void,
-                                        ptr::null_mut()) != CL_SUCCESS {
-            return Err(Error::Failed)
-        }
+        completed_timestamps(this.data() as cl_event)
+    }
+}
 
-        let mut end_time = 0;
-        if ffi::clGetEventProfilingInfo(this.data() as cl_event,
-                                        CL_PROFILING_COMMAND_END,
-                                        mem::size_of::<cl_ulong>(),
-                                        &mut end_time as *mut u64 as *mut c_void,
-                                        ptr::null_mut()) != CL_SUCCESS {
-            return Err(Error::Failed)
+fn poll_time_elapsed(this: &ProfileEvent, waker: &Waker) -> Poll<Result<u64, Error>> {
+    unsafe {
+        match sync_event::poll_event(this.data() as cl_event, waker) {
+            Poll::Ready(Ok(())) => {
+                let timestamps = completed_timestamps(this.data() as cl_event);
+                Poll::Ready(timestamps.map(|timestamps| timestamps.execution_time()))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
         }
-
-        Ok(end_time - start_time)
     }
 }
 
+fn cancel_poll_time_elapsed(_: &ProfileEvent) {}
+
+unsafe fn completed_timestamps(event: cl_event) -> Result<Timestamps, Error> {
+    Ok(Timestamps {
+        queued: try!(profiling_info(event, CL_PROFILING_COMMAND_QUEUED)),
+        submitted: try!(profiling_info(event, CL_PROFILING_COMMAND_SUBMIT)),
+        start: try!(profiling_info(event, CL_PROFILING_COMMAND_START)),
+        end: try!(profiling_info(event, CL_PROFILING_COMMAND_END)),
+    })
+}
+
+pub unsafe fn profiling_info(event: cl_event, param_name: cl_profiling_info) -> Result<u64, Error> {
+    let mut value: cl_ulong = 0;
+    if ffi::clGetEventProfilingInfo(event,
+                                    param_name,
+                                    mem::size_of::<cl_ulong>(),
+                                    &mut value as *mut cl_ulong as *mut c_void,
+                                    ptr::null_mut()) != CL_SUCCESS {
+        return Err(Error::Failed)
+    }
+    Ok(value)
+}
@@ -7,26 +7,193 @@
 // <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
-
-use api::cl::ffi::{self, CL_KERNEL_PROGRAM, cl_kernel, cl_program};
+use api::cl::ffi::{self, CL_KERNEL_ARG_NAME, CL_KERNEL_NUM_ARGS, CL_KERNEL_PROGRAM, CL_SUCCESS};
+use api::cl::ffi::{CL_KERNEL_ARG_ADDRESS_CONSTANT, CL_KERNEL_ARG_ADDRESS_GLOBAL};
+use api::cl::ffi::{CL_KERNEL_ARG_ADDRESS_LOCAL, CL_KERNEL_ARG_ADDRESS_QUALIFIER};
+use api::cl::ffi::{CL_KERNEL_ARG_TYPE_NAME, cl_kernel, cl_kernel_arg_address_qualifier};
+use api::cl::ffi::{cl_kernel_arg_info, cl_program, cl_uint};
+use error::Error;
 use program::{Program, ProgramFunctions};
+use queue::Uniform;
+use std::collections::HashMap;
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
 
 pub static PROGRAM_FUNCTIONS: ProgramFunctions = ProgramFunctions {
     destroy: destroy,
+    uniform_index: uniform_index,
 };
 
+/// The data behind an OpenCL program.
+struct ClProgram {
+    kernel: cl_kernel,
+    /// Maps each kernel argument name to its index, built once so that binding uniforms by name
+    /// needn't query the driver on every dispatch.
+    arg_indices: HashMap<String, u32>,
+    /// The kind of each kernel argument, by index.
+    arg_kinds: Vec<ArgKind>,
+}
+
+/// What a kernel argument can be set from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ArgKind {
+    Global,
+    Constant,
+    Local,
+    Private,
+    Image,
+    Sampler,
+}
+
+/// Wraps `kernel`, taking ownership of it. The kernel must have been built with
+/// `-cl-kernel-arg-info`, so that argument names are available.
+pub unsafe fn create(kernel: cl_kernel) -> Result<Program, Error> {
+    let (arg_indices, arg_kinds) = match arg_info(kernel) {
+        Ok(arg_info) => arg_info,
+        Err(err) => {
+            release_kernel(kernel);
+            return Err(err)
+        }
+    };
+
+    let program = Box::new(ClProgram {
+        kernel: kernel,
+        arg_indices: arg_indices,
+        arg_kinds: arg_kinds,
+    });
+    Ok(Program::from_raw_data(Box::into_raw(program) as usize, &PROGRAM_FUNCTIONS))
+}
+
+/// Returns the OpenCL kernel behind `program`.
+pub fn cl_kernel(program: &Program) -> cl_kernel {
+    program_data(program).kernel
+}
+
+fn program_data(program: &Program) -> &ClProgram {
+    unsafe {
+        &*(program.data() as *const ClProgram)
+    }
+}
+
 unsafe fn destroy(this: &Program) {
+    let program = Box::from_raw(this.data() as *mut ClProgram);
+    release_kernel(program.kernel);
+}
+
+unsafe fn release_kernel(kernel: cl_kernel) {
     let mut program = ptr::null_mut();
-    ffi::clGetKernelInfo(this.data() as cl_kernel,
+    ffi::clGetKernelInfo(kernel,
                          CL_KERNEL_PROGRAM,
                          mem::size_of::<cl_program>(),
                          &mut program as *mut cl_program as *mut c_void,
                          ptr::null_mut());
 
-    ffi::clReleaseKernel(this.data() as cl_kernel);
+    ffi::clReleaseKernel(kernel);
     ffi::clReleaseProgram(program);
 }
 
+fn uniform_index(this: &Program, name: &str, uniform: &Uniform) -> Result<u32, Error> {
+    let program = program_data(this);
+    let arg_index = match program.arg_indices.get(name) {
+        Some(&arg_index) => arg_index,
+        None => return Err(Error::UniformNotFound(name.to_owned())),
+    };
+
+    // A sampled image is passed as the image followed by its sampler.
+    let arg_kind = |index: u32| program.arg_kinds.get(index as usize).cloned();
+    let matches = match *uniform {
+        Uniform::Buffer(_) | Uniform::BufferSlice(_) => {
+            arg_kind(arg_index) == Some(ArgKind::Global)
+        }
+        Uniform::ConstantBuffer(_) => arg_kind(arg_index) == Some(ArgKind::Constant),
+        Uniform::LocalMemory(_) => arg_kind(arg_index) == Some(ArgKind::Local),
+        Uniform::Image(_) => arg_kind(arg_index) == Some(ArgKind::Image),
+        Uniform::SampledImage(..) => {
+            arg_kind(arg_index) == Some(ArgKind::Image) &&
+                arg_kind(arg_index + 1) == Some(ArgKind::Sampler)
+        }
+        _ => arg_kind(arg_index) == Some(ArgKind::Private),
+    };
+    if !matches {
+        return Err(Error::UniformMismatch(name.to_owned()))
+    }
+
+    Ok(arg_index)
+}
+
+unsafe fn arg_info(kernel: cl_kernel) -> Result<(HashMap<String, u32>, Vec<ArgKind>), Error> {
+    let mut arg_count: cl_uint = 0;
+    if ffi::clGetKernelInfo(kernel,
+                            CL_KERNEL_NUM_ARGS,
+                            mem::size_of::<cl_uint>(),
+                            &mut arg_count as *mut cl_uint as *mut c_void,
+                            ptr::null_mut()) != CL_SUCCESS {
+        return Err(Error::Failed)
+    }
+
+    let mut arg_indices = HashMap::new();
+    let mut arg_kinds = Vec::with_capacity(arg_count as usize);
+    for arg_index in 0..arg_count {
+        if let Ok(arg_name) = String::from_utf8(try!(arg_string(kernel,
+                                                                 arg_index,
+                                                                 CL_KERNEL_ARG_NAME))) {
+            arg_indices.insert(arg_name, arg_index);
+        }
+
+        let mut address_qualifier: cl_kernel_arg_address_qualifier = 0;
+        if ffi::clGetKernelArgInfo(kernel,
+                                   arg_index,
+                                   CL_KERNEL_ARG_ADDRESS_QUALIFIER,
+                                   mem::size_of::<cl_kernel_arg_address_qualifier>(),
+                                   &mut address_qualifier as *mut _ as *mut c_void,
+                                   ptr::null_mut()) != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+
+        // Images and samplers are told apart by their type names, since drivers disagree on which
+        // address space they report for them.
+        let type_name = try!(arg_string(kernel, arg_index, CL_KERNEL_ARG_TYPE_NAME));
+        arg_kinds.push(if type_name.starts_with(b"image") {
+            ArgKind::Image
+        } else if type_name == b"sampler_t" {
+            ArgKind::Sampler
+        } else {
+            match address_qualifier {
+                CL_KERNEL_ARG_ADDRESS_GLOBAL => ArgKind::Global,
+                CL_KERNEL_ARG_ADDRESS_CONSTANT => ArgKind::Constant,
+                CL_KERNEL_ARG_ADDRESS_LOCAL => ArgKind::Local,
+                _ => ArgKind::Private,
+            }
+        });
+    }
+
+    Ok((arg_indices, arg_kinds))
+}
+
+// Returns a string-valued piece of information about a kernel argument, without the trailing NUL.
+unsafe fn arg_string(kernel: cl_kernel, arg_index: cl_uint, param_name: cl_kernel_arg_info)
+                     -> Result<Vec<u8>, Error> {
+    let mut size = 0;
+    if ffi::clGetKernelArgInfo(kernel,
+                               arg_index,
+                               param_name,
+                               0,
+                               ptr::null_mut(),
+                               &mut size) != CL_SUCCESS {
+        return Err(Error::Failed)
+    }
+
+    let mut string = vec![0u8; size];
+    if ffi::clGetKernelArgInfo(kernel,
+                               arg_index,
+                               param_name,
+                               string.len(),
+                               string.as_mut_ptr() as *mut c_void,
+                               ptr::null_mut()) != CL_SUCCESS {
+        return Err(Error::Failed)
+    }
+
+    string.pop();
+    Ok(string)
+}
@@ -8,19 +8,25 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::cl::ffi::{self, CL_IMAGE_DEPTH, CL_IMAGE_HEIGHT, CL_IMAGE_WIDTH, CL_SUCCESS, CL_TRUE};
-use api::cl::ffi::{cl_command_queue, cl_event, cl_kernel, cl_mem};
+use api::cl::buffer as cl_buffer;
+use api::cl::ffi::{self, CL_IMAGE_DEPTH, CL_IMAGE_HEIGHT, CL_IMAGE_WIDTH};
+use api::cl::ffi::{CL_SUCCESS, CL_TRUE, cl_command_queue, cl_event, cl_int};
+use api::cl::ffi::{cl_mem, cl_sampler, cl_uint};
+use api::cl::image as cl_image;
 use api::cl::profile_event::PROFILE_EVENT_FUNCTIONS;
+use api::cl::program as cl_program;
 use api::cl::sync_event::SYNC_EVENT_FUNCTIONS;
 use buffer::Buffer;
 use error::Error;
+use gl;
 use image::{Color, Image};
 use profile_event::ProfileEvent;
 use program::Program;
-use queue::{Queue, QueueFunctions, Uniform};
+use queue::{BarrierScope, Queue, QueueFunctions, Uniform};
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
+use std::sync::Mutex;
 use sync_event::SyncEvent;
 
 pub static QUEUE_FUNCTIONS: QueueFunctions = QueueFunctions {
@@ -31,15 +37,61 @@ pub static QUEUE_FUNCTIONS: QueueFunctions = QueueFunctions {
     submit_clear: submit_clear,
     submit_read_buffer: submit_read_buffer,
     submit_sync_event: submit_sync_event,
+    submit_barrier: submit_barrier,
 };
 
+/// The data behind an OpenCL queue.
+pub struct ClQueue {
+    pub queue: cl_command_queue,
+    /// Whether the queue was created with `CL_QUEUE_PROFILING_ENABLE`, cached so that submitting
+    /// a command needn't ask the driver.
+    pub profiling: bool,
+}
+
+// Every live command queue, so that commands enqueued on any of them can be flushed when a host
+// buffer is destroyed.
+static QUEUES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+pub fn create(queue: cl_command_queue, profiling: bool) -> Queue {
+    QUEUES.lock().unwrap().push(queue as usize);
+    let queue = Box::new(ClQueue {
+        queue: queue,
+        profiling: profiling,
+    });
+    unsafe {
+        Queue::from_raw_data(Box::into_raw(queue) as usize, &QUEUE_FUNCTIONS)
+    }
+}
+
+/// Returns the OpenCL command queue behind `queue`.
+pub fn cl_command_queue(queue: &Queue) -> cl_command_queue {
+    cl_queue(queue).queue
+}
+
+fn cl_queue(queue: &Queue) -> &ClQueue {
+    unsafe {
+        &*(queue.data() as *const ClQueue)
+    }
+}
+
 unsafe fn destroy(this: &Queue) {
-    ffi::clReleaseCommandQueue(this.data() as cl_command_queue);
+    let queue = Box::from_raw(this.data() as *mut ClQueue);
+    QUEUES.lock().unwrap().retain(|&other| other != queue.queue as usize);
+    ffi::clReleaseCommandQueue(queue.queue);
+}
+
+/// Submits the commands enqueued on every live queue, so that they are sure to complete.
+pub fn flush_all() {
+    for &queue in QUEUES.lock().unwrap().iter() {
+        unsafe {
+            ffi::clFlush(queue as cl_command_queue);
+        }
+    }
 }
 
 fn flush(this: &Queue) -> Result<(), Error> {
     unsafe {
-        if ffi::clFlush(this.data() as cl_command_queue) == CL_SUCCESS {
+        if ffi::clFlush(cl_command_queue(this)) == CL_SUCCESS {
             Ok(())
         } else {
             Err(Error::Failed)
@@ -49,7 +101,7 @@ fn flush(this: &Queue) -> Result<(), Error> {
 
 fn finish(this: &Queue) -> Result<(), Error> {
     unsafe {
-        if ffi::clFinish(this.data() as cl_command_queue) == CL_SUCCESS {
+        if ffi::clFinish(cl_command_queue(this)) == CL_SUCCESS {
             Ok(())
         } else {
             Err(Error::Failed)
@@ -64,34 +116,88 @@ fn submit_compute(this: &Queue,
                   events: &[SyncEvent])
                   -> Result<ProfileEvent, Error> {
     unsafe {
+        let mut memory_objects = vec![];
+
         for &(uniform_index, ref uniform) in uniforms {
             let err = match *uniform {
-                Uniform::Buffer(buffer) => {
+                Uniform::Buffer(buffer) | Uniform::ConstantBuffer(buffer) => {
                     let data = buffer.data();
-                    ffi::clSetKernelArg(program.data() as cl_kernel,
+                    if cl_buffer::is_imported(buffer) {
+                        memory_objects.push(data as cl_mem)
+                    }
+                    ffi::clSetKernelArg(cl_program::cl_kernel(program),
                                         uniform_index,
                                         mem::size_of::<cl_mem>(),
                                         &data as *const usize as *const c_void)
                 }
+                Uniform::BufferSlice(slice) => {
+                    let sub_buffer = try!(cl_buffer::sub_buffer(&slice));
+                    if cl_buffer::is_imported(slice.buffer()) {
+                        memory_objects.push(slice.buffer().data() as cl_mem)
+                    }
+                    set_kernel_arg(program, uniform_index, &sub_buffer)
+                }
                 Uniform::Image(image) => {
                     let data = image.data()[0];
-                    ffi::clSetKernelArg(program.data() as cl_kernel,
+                    if cl_image::is_imported(image) {
+                        memory_objects.push(data as cl_mem)
+                    }
+                    ffi::clSetKernelArg(cl_program::cl_kernel(program),
                                         uniform_index,
                                         mem::size_of::<cl_mem>(),
                                         &data as *const usize as *const c_void)
                 }
+                Uniform::SampledImage(image, sampler) => {
+                    if cl_image::is_imported(image) {
+                        memory_objects.push(image.data()[0] as cl_mem)
+                    }
+                    let image = image.data()[0] as cl_mem;
+                    let sampler = sampler.data() as cl_sampler;
+                    match set_kernel_arg(program, uniform_index, &image) {
+                        CL_SUCCESS => set_kernel_arg(program, uniform_index + 1, &sampler),
+                        err => err,
+                    }
+                }
                 Uniform::U32(ref value) => {
-                    ffi::clSetKernelArg(program.data() as cl_kernel,
+                    ffi::clSetKernelArg(cl_program::cl_kernel(program),
                                         uniform_index,
                                         mem::size_of::<u32>(),
                                         value as *const u32 as *const c_void)
                 }
                 Uniform::UVec4(ref value) => {
-                    ffi::clSetKernelArg(program.data() as cl_kernel,
+                    ffi::clSetKernelArg(cl_program::cl_kernel(program),
                                         uniform_index,
                                         mem::size_of::<[u32; 4]>(),
                                         value as *const [u32; 4] as *const c_void)
                 }
+                Uniform::I32(ref value) => set_kernel_arg(program, uniform_index, value),
+                Uniform::IVec2(ref value) => set_kernel_arg(program, uniform_index, value),
+                Uniform::IVec3(value) => {
+                    // OpenCL 3-component vectors are padded to the size of 4-component ones.
+                    let value = [value[0], value[1], value[2], 0];
+                    set_kernel_arg(program, uniform_index, &value)
+                }
+                Uniform::IVec4(ref value) => set_kernel_arg(program, uniform_index, value),
+                Uniform::F32(ref value) => set_kernel_arg(program, uniform_index, value),
+                Uniform::Vec2(ref value) => set_kernel_arg(program, uniform_index, value),
+                Uniform::Vec3(value) => {
+                    let value = [value[0], value[1], value[2], 0.0];
+                    set_kernel_arg(program, uniform_index, &value)
+                }
+                Uniform::Vec4(ref value) => set_kernel_arg(program, uniform_index, value),
+                Uniform::Mat4(ref value) => set_kernel_arg(program, uniform_index, value),
+                Uniform::Bytes(bytes) => {
+                    ffi::clSetKernelArg(cl_program::cl_kernel(program),
+                                        uniform_index,
+                                        bytes.len(),
+                                        bytes.as_ptr() as *const c_void)
+                }
+                Uniform::LocalMemory(size) => {
+                    ffi::clSetKernelArg(cl_program::cl_kernel(program),
+                                        uniform_index,
+                                        size,
+                                        ptr::null())
+                }
             };
             if err != CL_SUCCESS {
                 return Err(Error::Failed)
@@ -103,40 +209,53 @@ fn submit_compute(this: &Queue,
             global_work_size[dimension] = group_size as usize
         }
 
-        let event_wait_list: Vec<_> = events.iter()
-                                            .map(|event| event.data() as cl_event)
-                                            .collect();
-        let event_wait_list_ptr = if event_wait_list.is_empty() {
-            ptr::null()
-        } else {
-            event_wait_list.as_ptr()
-        };
+        // An object may be passed more than once, but must be acquired only once.
+        memory_objects.sort();
+        memory_objects.dedup();
+        let gl_objects = try!(GlObjects::acquire(this, &memory_objects));
+        let event_wait_list = gl_objects.wait_list(events);
 
         let mut event = ptr::null_mut();
+        let event_ptr = command_event_ptr(this, &gl_objects, &mut event);
 
-        if ffi::clEnqueueNDRangeKernel(this.data() as cl_command_queue,
-                                       program.data() as cl_kernel,
+        if ffi::clEnqueueNDRangeKernel(cl_command_queue(this),
+                                       cl_program::cl_kernel(program),
                                        num_groups.len() as u32,
                                        ptr::null(),
                                        global_work_size.as_mut_ptr(),
                                        ptr::null(),
                                        event_wait_list.len() as u32,
-                                       event_wait_list_ptr,
-                                       &mut event) != CL_SUCCESS {
+                                       event_wait_list_ptr(&event_wait_list),
+                                       event_ptr) != CL_SUCCESS {
             return Err(Error::Failed)
         }
 
-        Ok(ProfileEvent::from_raw_data(event as usize, &PROFILE_EVENT_FUNCTIONS))
+        gl_objects.release_after(event);
+        Ok(command_event(this, event))
     }
 }
 
 fn submit_clear(this: &Queue, image: &Image, color: &Color, events: &[SyncEvent])
                 -> Result<ProfileEvent, Error> {
     unsafe {
-        let colors = match *color {
+        let color = match *color {
             Color::UInt(r, g, b, a) => [r, g, b, a],
         };
 
+        // OpenCL fills images that aren't integer ones with floating-point colors, which are
+        // normalized for normalized formats.
+        let format = try!(image.format());
+        let fill_color: [u32; 4] = if format.is_integer() {
+            color
+        } else if format.is_floating_point() {
+            mem::transmute([color[0] as f32, color[1] as f32, color[2] as f32, color[3] as f32])
+        } else {
+            mem::transmute([color[0] as u8 as f32 / 255.0,
+                            color[1] as u8 as f32 / 255.0,
+                            color[2] as u8 as f32 / 255.0,
+                            color[3] as u8 as f32 / 255.0])
+        };
+
         let origin = [0, 0, 0];
 
         let mut size = [0, 0, 0];
@@ -161,29 +280,30 @@ fn submit_clear(this: &Queue, image: &Image, color: &Color, events: &[SyncEvent]
             }
         }
 
-        let event_wait_list: Vec<_> = events.iter()
-                                            .map(|event| event.data() as cl_event)
-                                            .collect();
-        let event_wait_list_ptr = if event_wait_list.is_empty() {
-            ptr::null()
+        let imported = if cl_image::is_imported(image) {
+            vec![image.data()[0] as cl_mem]
         } else {
-            event_wait_list.as_ptr()
+            vec![]
         };
+        let gl_objects = try!(GlObjects::acquire(this, &imported));
+        let event_wait_list = gl_objects.wait_list(events);
 
         let mut event = ptr::null_mut();
+        let event_ptr = command_event_ptr(this, &gl_objects, &mut event);
 
-        if ffi::clEnqueueFillImage(this.data() as cl_command_queue,
+        if ffi::clEnqueueFillImage(cl_command_queue(this),
                                    image.data()[0] as cl_mem,
-                                   colors.as_ptr() as *const c_void,
+                                   fill_color.as_ptr() as *const c_void,
                                    origin.as_ptr(),
                                    size.as_mut_ptr(),
                                    event_wait_list.len() as u32,
-                                   event_wait_list_ptr,
-                                   &mut event) == CL_SUCCESS {
-            Ok(ProfileEvent::from_raw_data(event as usize, &PROFILE_EVENT_FUNCTIONS))
-        } else {
-            Err(Error::Failed)
+                                   event_wait_list_ptr(&event_wait_list),
+                                   event_ptr) != CL_SUCCESS {
+            return Err(Error::Failed)
         }
+
+        gl_objects.release_after(event);
+        Ok(command_event(this, event))
     }
 }
 
@@ -194,37 +314,38 @@ fn submit_read_buffer(this: &Queue,
                       events: &[SyncEvent])
                       -> Result<ProfileEvent, Error> {
     unsafe {
-        let event_wait_list: Vec<_> = events.iter()
-                                            .map(|event| event.data() as cl_event)
-                                            .collect();
-        let event_wait_list_ptr = if event_wait_list.is_empty() {
-            ptr::null()
+        let imported = if cl_buffer::is_imported(buffer) {
+            vec![buffer.data() as cl_mem]
         } else {
-            event_wait_list.as_ptr()
+            vec![]
         };
+        let gl_objects = try!(GlObjects::acquire(this, &imported));
+        let event_wait_list = gl_objects.wait_list(events);
 
         let mut event = ptr::null_mut();
+        let event_ptr = command_event_ptr(this, &gl_objects, &mut event);
 
-        if ffi::clEnqueueReadBuffer(this.data() as cl_command_queue,
+        if ffi::clEnqueueReadBuffer(cl_command_queue(this),
                                     buffer.data() as cl_mem,
                                     CL_TRUE,
                                     start,
                                     dest.len(),
                                     dest.as_mut_ptr() as *mut c_void,
                                     event_wait_list.len() as u32,
-                                    event_wait_list_ptr,
-                                    &mut event) == CL_SUCCESS {
-            Ok(ProfileEvent::from_raw_data(event as usize, &PROFILE_EVENT_FUNCTIONS))
-        } else {
-            Err(Error::Failed)
+                                    event_wait_list_ptr(&event_wait_list),
+                                    event_ptr) != CL_SUCCESS {
+            return Err(Error::Failed)
         }
+
+        gl_objects.release_after(event);
+        Ok(command_event(this, event))
     }
 }
 
 fn submit_sync_event(this: &Queue) -> Result<SyncEvent, Error> {
     unsafe {
         let mut event = ptr::null_mut();
-        if ffi::clEnqueueMarker(this.data() as cl_command_queue, &mut event) == CL_SUCCESS {
+        if ffi::clEnqueueMarker(cl_command_queue(this), &mut event) == CL_SUCCESS {
             Ok(SyncEvent::from_raw_data(event as usize, &SYNC_EVENT_FUNCTIONS))
         } else {
             Err(Error::Failed)
@@ -232,3 +353,168 @@ fn submit_sync_event(this: &Queue) -> Result<SyncEvent, Error> {
     }
 }
 
+// OpenCL makes the results of a command visible to every later command that depends on it, so
+// there is no finer-grained barrier to issue and automatic barriers need no tracking. On in-order
+// queues this is a no-op; on out-of-order queues it orders all later commands after earlier ones.
+fn submit_barrier(this: &Queue, _: BarrierScope) -> Result<(), Error> {
+    unsafe {
+        if ffi::clEnqueueBarrier(cl_command_queue(this)) == CL_SUCCESS {
+            Ok(())
+        } else {
+            Err(Error::Failed)
+        }
+    }
+}
+
+// The memory objects used by a command that were imported from OpenGL. These must be acquired
+// from OpenGL before the command and released back to it after. Commands on out-of-order queues
+// may run in any order, so the command waits on the acquire and the release waits on the command.
+pub struct GlObjects {
+    queue: cl_command_queue,
+    objects: Vec<cl_mem>,
+    acquired: cl_event,
+}
+
+impl GlObjects {
+    pub unsafe fn acquire(queue: &Queue, objects: &[cl_mem]) -> Result<GlObjects, Error> {
+        let queue = cl_command_queue(queue);
+        let acquired = try!(acquire_gl_objects(queue, objects, &[]));
+        Ok(GlObjects {
+            queue: queue,
+            objects: objects.to_vec(),
+            acquired: acquired,
+        })
+    }
+
+    /// Returns the events that a command using the objects must wait on: `events`, along with the
+    /// acquire.
+    pub fn wait_list(&self, events: &[SyncEvent]) -> Vec<cl_event> {
+        let mut wait_list: Vec<_> = events.iter().map(|event| event.data() as cl_event).collect();
+        if !self.acquired.is_null() {
+            wait_list.push(self.acquired)
+        }
+        wait_list
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Releases the objects once the command that `event` belongs to has completed.
+    pub unsafe fn release_after(mut self, event: cl_event) {
+        release_gl_objects(self.queue, &self.objects, event);
+        self.objects.clear()
+    }
+}
+
+// If no command was enqueued, the objects are released once the acquire has completed.
+impl Drop for GlObjects {
+    fn drop(&mut self) {
+        unsafe {
+            release_gl_objects(self.queue, &self.objects, self.acquired);
+            if !self.acquired.is_null() {
+                ffi::clReleaseEvent(self.acquired);
+            }
+        }
+    }
+}
+
+/// Acquires memory objects imported from OpenGL for use by the commands that follow, once the
+/// events in `wait_list` have completed.
+///
+/// Returns the event of the acquire, which those commands must wait on and which the caller must
+/// release, or null if there were no objects. Each object must be handed back with
+/// `release_gl_objects()`.
+pub unsafe fn acquire_gl_objects(queue: cl_command_queue,
+                                 objects: &[cl_mem],
+                                 wait_list: &[cl_event])
+                                 -> Result<cl_event, Error> {
+    if objects.is_empty() {
+        return Ok(ptr::null_mut())
+    }
+
+    // OpenGL must have submitted the commands that use the objects first.
+    gl::Flush();
+    let mut event = ptr::null_mut();
+    if ffi::clEnqueueAcquireGLObjects(queue,
+                                      objects.len() as cl_uint,
+                                      objects.as_ptr(),
+                                      wait_list.len() as cl_uint,
+                                      event_wait_list_ptr(wait_list),
+                                      &mut event) == CL_SUCCESS {
+        Ok(event)
+    } else {
+        Err(Error::Failed)
+    }
+}
+
+/// Releases memory objects acquired with `acquire_gl_objects()` back to OpenGL once the command
+/// that `event` belongs to has completed. `event` may be null if there is nothing to wait on.
+pub unsafe fn release_gl_objects(queue: cl_command_queue, objects: &[cl_mem], event: cl_event) {
+    if objects.is_empty() {
+        return
+    }
+
+    let wait_list = if event.is_null() {
+        vec![]
+    } else {
+        vec![event]
+    };
+    ffi::clEnqueueReleaseGLObjects(queue,
+                                   objects.len() as cl_uint,
+                                   objects.as_ptr(),
+                                   wait_list.len() as cl_uint,
+                                   event_wait_list_ptr(&wait_list),
+                                   ptr::null_mut());
+    // Submit the release so that OpenGL commands issued afterward see the results.
+    ffi::clFlush(queue);
+}
+
+/// Returns a pointer to `wait_list` suitable for passing to OpenCL, which wants null rather than
+/// a pointer to an empty list.
+pub fn event_wait_list_ptr(wait_list: &[cl_event]) -> *const cl_event {
+    if wait_list.is_empty() {
+        ptr::null()
+    } else {
+        wait_list.as_ptr()
+    }
+}
+
+unsafe fn set_kernel_arg<T>(program: &Program, index: u32, value: &T) -> cl_int {
+    ffi::clSetKernelArg(cl_program::cl_kernel(program),
+                        index,
+                        mem::size_of::<T>(),
+                        value as *const T as *const c_void)
+}
+
+// Returns where a command should store its event: `event` if profiling is enabled on this queue
+// or the release of OpenGL objects must wait on the command, or null otherwise so that OpenCL
+// doesn't have to create one.
+fn command_event_ptr(this: &Queue, gl_objects: &GlObjects, event: &mut cl_event)
+                     -> *mut cl_event {
+    if cl_queue(this).profiling || !gl_objects.is_empty() {
+        event
+    } else {
+        ptr::null_mut()
+    }
+}
+
+// Returns the profile event of a command, releasing its event if it was only needed to order the
+// release of OpenGL objects.
+unsafe fn command_event(this: &Queue, event: cl_event) -> ProfileEvent {
+    if cl_queue(this).profiling {
+        return profile_event(event)
+    }
+    if !event.is_null() {
+        ffi::clReleaseEvent(event);
+    }
+    ProfileEvent::null()
+}
+
+unsafe fn profile_event(event: cl_event) -> ProfileEvent {
+    if event.is_null() {
+        ProfileEvent::null()
+    } else {
+        ProfileEvent::from_raw_data(event as usize, &PROFILE_EVENT_FUNCTIONS)
+    }
+}
@@ -8,16 +8,44 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::cl::ffi::{self, CL_SUCCESS, cl_event};
+use api::cl::ffi::{self, CL_COMPLETE, CL_EVENT_COMMAND_EXECUTION_STATUS};
+use api::cl::ffi::{CL_EVENT_COMMAND_QUEUE, CL_QUEUED, CL_RUNNING, CL_SUBMITTED, CL_SUCCESS};
+use api::cl::ffi::{cl_command_queue, cl_event, cl_int};
 use error::Error;
-use sync_event::{SyncEvent, SyncEventFunctions};
+use std::collections::BTreeMap;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+use sync_event::{Status, SyncEvent, SyncEventFunctions};
 
 pub static SYNC_EVENT_FUNCTIONS: SyncEventFunctions = SyncEventFunctions {
     destroy: destroy,
     wait: wait,
+    wait_timeout: wait_timeout,
+    status: status,
+    signal: signal,
+    poll: poll,
+    cancel_poll: cancel_poll,
 };
 
+// The tasks waiting on each event, keyed by event.
+static EVENT_WAITERS: Mutex<BTreeMap<usize, Arc<Waiters>>> = Mutex::new(BTreeMap::new());
+
+// Wakes a thread blocked in `wait_timeout()`.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<ThreadWaker>) {
+        self.0.unpark()
+    }
+}
+
 unsafe fn destroy(this: &SyncEvent) {
+    forget_event(this.data() as cl_event);
     ffi::clReleaseEvent(this.data() as cl_event);
 }
 
@@ -32,3 +60,191 @@ fn wait(this: &SyncEvent) -> Result<(), Error> {
     }
 }
 
+// OpenCL has no timed wait, so register a completion callback that unparks this thread and park
+// until either it fires or the deadline passes.
+fn wait_timeout(this: &SyncEvent, timeout: Duration) -> Result<(), Error> {
+    unsafe {
+        // A timeout too long to represent is as good as none.
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            None => return wait(this),
+        };
+
+        let event = this.data() as cl_event;
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+
+        // Unlike `clWaitForEvents()`, callbacks don't flush the queue for us.
+        let mut queue: cl_command_queue = ptr::null_mut();
+        if ffi::clGetEventInfo(event,
+                               CL_EVENT_COMMAND_QUEUE,
+                               mem::size_of::<cl_command_queue>(),
+                               &mut queue as *mut cl_command_queue as *mut c_void,
+                               ptr::null_mut()) != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+        if !queue.is_null() && ffi::clFlush(queue) != CL_SUCCESS {
+            return Err(Error::Failed)
+        }
+
+        loop {
+            if let Poll::Ready(result) = poll_event(event, &waker) {
+                return result
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                // Don't leave this thread's waker behind in case the event never completes.
+                forget_waker(event, &waker);
+                return Err(Error::Timeout)
+            }
+            thread::park_timeout(deadline - now)
+        }
+    }
+}
+
+fn status(this: &SyncEvent) -> Result<Status, Error> {
+    unsafe {
+        execution_status(this.data() as cl_event)
+    }
+}
+
+// `clSetUserEventStatus()` rejects events that aren't user events, as well as user events that
+// have already been signaled.
+fn signal(this: &SyncEvent) -> Result<(), Error> {
+    unsafe {
+        if ffi::clSetUserEventStatus(this.data() as cl_event, CL_COMPLETE) == CL_SUCCESS {
+            Ok(())
+        } else {
+            Err(Error::Failed)
+        }
+    }
+}
+
+fn poll(this: &SyncEvent, waker: &Waker) -> Poll<Result<(), Error>> {
+    unsafe {
+        poll_event(this.data() as cl_event, waker)
+    }
+}
+
+// The completion callback wakes and forgets the waiting tasks once the event completes, so a task
+// that stops waiting early needs no cleanup.
+fn cancel_poll(_: &SyncEvent) {}
+
+/// Checks whether `event` has completed, arranging for `waker` to be woken when it does if it has
+/// not.
+///
+/// However often an event is polled, only one completion callback is registered for it.
+pub unsafe fn poll_event(event: cl_event, waker: &Waker) -> Poll<Result<(), Error>> {
+    match execution_status(event) {
+        Ok(Status::Complete) => return Poll::Ready(Ok(())),
+        Ok(Status::Error) => return Poll::Ready(Err(Error::Failed)),
+        Err(err) => return Poll::Ready(Err(err)),
+        Ok(Status::Queued) | Ok(Status::Running) => {}
+    }
+
+    let waiters = {
+        let mut event_waiters = EVENT_WAITERS.lock().unwrap();
+        if let Some(waiters) = event_waiters.get(&(event as usize)) {
+            waiters.add(waker);
+            return Poll::Pending
+        }
+
+        let waiters = Arc::new(Waiters {
+            state: Mutex::new(WaitersState {
+                completed: false,
+                wakers: vec![waker.clone()],
+            }),
+        });
+        event_waiters.insert(event as usize, waiters.clone());
+        waiters
+    };
+
+    // If the event completes before the callback is registered, OpenCL calls it immediately, so
+    // there's no race here. That's also why the lock must not be held while registering.
+    let user_data = Arc::into_raw(waiters) as *mut c_void;
+    if ffi::clSetEventCallback(event, CL_COMPLETE, Some(wake), user_data) != CL_SUCCESS {
+        let waiters = Arc::from_raw(user_data as *const Waiters);
+        remove_waiters(event, &waiters);
+        return Poll::Ready(Err(Error::Failed))
+    }
+
+    Poll::Pending
+}
+
+/// Forgets the wakers registered for `event`. Call this when releasing an event, since OpenCL may
+/// reuse its address.
+pub fn forget_event(event: cl_event) {
+    EVENT_WAITERS.lock().unwrap().remove(&(event as usize));
+}
+
+// Removes `waker` from the wakers registered for `event`.
+fn forget_waker(event: cl_event, waker: &Waker) {
+    if let Some(waiters) = EVENT_WAITERS.lock().unwrap().get(&(event as usize)) {
+        waiters.state.lock().unwrap().wakers.retain(|other| !other.will_wake(waker))
+    }
+}
+
+// The tasks waiting for an event to complete, shared with its completion callback.
+struct Waiters {
+    state: Mutex<WaitersState>,
+}
+
+struct WaitersState {
+    completed: bool,
+    wakers: Vec<Waker>,
+}
+
+impl Waiters {
+    fn add(&self, waker: &Waker) {
+        let mut state = self.state.lock().unwrap();
+        if state.completed {
+            // The callback ran after we checked the status, so have the task poll again.
+            waker.wake_by_ref()
+        } else if !state.wakers.iter().any(|other| other.will_wake(waker)) {
+            state.wakers.push(waker.clone())
+        }
+    }
+}
+
+unsafe fn remove_waiters(event: cl_event, waiters: &Arc<Waiters>) {
+    let mut event_waiters = EVENT_WAITERS.lock().unwrap();
+    let is_current = event_waiters.get(&(event as usize))
+                                  .map_or(false, |current| Arc::ptr_eq(current, waiters));
+    if is_current {
+        event_waiters.remove(&(event as usize));
+    }
+}
+
+unsafe fn execution_status(event: cl_event) -> Result<Status, Error> {
+    let mut status: cl_int = 0;
+    if ffi::clGetEventInfo(event,
+                           CL_EVENT_COMMAND_EXECUTION_STATUS,
+                           mem::size_of::<cl_int>(),
+                           &mut status as *mut cl_int as *mut c_void,
+                           ptr::null_mut()) != CL_SUCCESS {
+        return Err(Error::Failed)
+    }
+
+    // Negative statuses are error codes.
+    match status {
+        CL_QUEUED | CL_SUBMITTED => Ok(Status::Queued),
+        CL_RUNNING => Ok(Status::Running),
+        CL_COMPLETE => Ok(Status::Complete),
+        status if status < 0 => Ok(Status::Error),
+        _ => Err(Error::Failed),
+    }
+}
+
+unsafe extern "C" fn wake(event: cl_event, _: cl_int, user_data: *mut c_void) {
+    let waiters = Arc::from_raw(user_data as *const Waiters);
+    remove_waiters(event, &waiters);
+
+    let wakers = {
+        let mut state = waiters.state.lock().unwrap();
+        state.completed = true;
+        mem::replace(&mut state.wakers, vec![])
+    };
+    for waker in wakers {
+        waker.wake()
+    }
+}
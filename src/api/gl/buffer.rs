@@ -8,12 +8,35 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use buffer::{Buffer, BufferFunctions};
-use gl::types::GLuint;
+use api::gl::queue as gl_queue;
+use buffer::{Buffer, BufferFunctions, ExternalBuffer, MapMode};
+use error::Error;
+use gl::types::{GLintptr, GLsizeiptr, GLuint};
 use gl;
+use queue::Queue;
+use std::ops::Range;
 
 pub static BUFFER_FUNCTIONS: BufferFunctions = BufferFunctions {
     destroy: destroy,
+    map: map,
+    unmap: unmap,
+    copy_to: copy_to,
+};
+
+// Buffers that wrap buffer objects owned by the caller.
+pub static IMPORTED_BUFFER_FUNCTIONS: BufferFunctions = BufferFunctions {
+    destroy: destroy_imported,
+    map: map,
+    unmap: unmap,
+    copy_to: copy_to,
+};
+
+// Buffers that wrap caller-owned memory with `GL_AMD_pinned_memory`.
+pub static HOST_BUFFER_FUNCTIONS: BufferFunctions = BufferFunctions {
+    destroy: destroy_host_buffer,
+    map: map,
+    unmap: unmap,
+    copy_to: copy_to,
 };
 
 unsafe fn destroy(this: &Buffer) {
@@ -21,3 +44,69 @@ unsafe fn destroy(this: &Buffer) {
     gl::DeleteBuffers(1, &mut buffer)
 }
 
+unsafe fn destroy_imported(_: &Buffer) {}
+
+// The caller may free the memory as soon as this returns, so wait for the GPU to stop using it.
+unsafe fn destroy_host_buffer(this: &Buffer) {
+    gl::Finish();
+    destroy(this)
+}
+
+fn map(this: &Buffer, queue: &Queue, range: Range<usize>, mode: MapMode)
+       -> Result<*mut u8, Error> {
+    gl_queue::prepare_to_update_buffer(queue, this);
+
+    let access = match mode {
+        MapMode::Read => gl::MAP_READ_BIT,
+        MapMode::Write => gl::MAP_WRITE_BIT | gl::MAP_INVALIDATE_RANGE_BIT,
+        MapMode::ReadWrite => gl::MAP_READ_BIT | gl::MAP_WRITE_BIT,
+    };
+
+    unsafe {
+        gl::BindBuffer(gl::COPY_WRITE_BUFFER, this.data() as GLuint);
+        let ptr = gl::MapBufferRange(gl::COPY_WRITE_BUFFER,
+                                     range.start as GLintptr,
+                                     (range.end - range.start) as GLsizeiptr,
+                                     access);
+        if !ptr.is_null() {
+            Ok(ptr as *mut u8)
+        } else {
+            Err(Error::Failed)
+        }
+    }
+}
+
+unsafe fn unmap(this: &Buffer, _: &Queue, _: *mut u8) {
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, this.data() as GLuint);
+    gl::UnmapBuffer(gl::COPY_WRITE_BUFFER);
+}
+
+fn copy_to(this: &Buffer, queue: &Queue, external_buffer: &ExternalBuffer) -> Result<(), Error> {
+    unsafe {
+        match *external_buffer {
+            ExternalBuffer::GlBuffer(buffer) => {
+                if gl_buffer_size(buffer) < this.size() {
+                    return Err(Error::OutOfBounds)
+                }
+
+                gl_queue::prepare_to_update_buffer(queue, this);
+                gl::BindBuffer(gl::COPY_WRITE_BUFFER, buffer);
+                gl::BindBuffer(gl::COPY_READ_BUFFER, this.data() as GLuint);
+                gl::CopyBufferSubData(gl::COPY_READ_BUFFER,
+                                      gl::COPY_WRITE_BUFFER,
+                                      0,
+                                      0,
+                                      this.size() as GLsizeiptr);
+                Ok(())
+            }
+        }
+    }
+}
+
+// Binds `buffer` to `GL_COPY_WRITE_BUFFER` and returns the size of its store.
+pub unsafe fn gl_buffer_size(buffer: GLuint) -> usize {
+    gl::BindBuffer(gl::COPY_WRITE_BUFFER, buffer);
+    let mut size = 0;
+    gl::GetBufferParameteriv(gl::COPY_WRITE_BUFFER, gl::BUFFER_SIZE, &mut size);
+    size as usize
+}
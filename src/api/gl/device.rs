@@ -8,38 +8,103 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::gl::buffer::BUFFER_FUNCTIONS;
-use api::gl::image::IMAGE_FUNCTIONS;
+use api::gl::buffer::{self as gl_buffer, BUFFER_FUNCTIONS, HOST_BUFFER_FUNCTIONS};
+use api::gl::buffer::IMPORTED_BUFFER_FUNCTIONS;
+use api::gl::image::{self as gl_image, IMAGE_FUNCTIONS, IMPORTED_IMAGE_FUNCTIONS};
+use api::gl::profile_event;
 use api::gl::program::PROGRAM_FUNCTIONS;
-use api::gl::queue::QUEUE_FUNCTIONS;
-use buffer::{Buffer, BufferData, Protection};
+use api::gl::queue;
+use api::gl::sampler::SAMPLER_FUNCTIONS;
+use api::gl::sync_event;
+use buffer::{Buffer, BufferData, ExternalBuffer, MemoryLocation, Protection};
 use device::{Device, DeviceFunctions};
 use error::Error;
 use euclid::Size2D;
-use gl::types::GLint;
+use gl::types::{GLenum, GLint, GLsizeiptr, GLuint};
 use gl;
-use image::{Format, Image};
+use image::{ExternalImage, Format, Image};
+use memory::DriverMemoryInfo;
+use profile_event::ClockCalibration;
 use program::Program;
-use queue::Queue;
-use std::os::raw::c_void;
+use queue::{Queue, QueueOptions};
+use sampler::{AddressMode, Filter, Sampler};
+use sync_event::SyncEvent;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
 use std::ptr;
+use std::time::Instant;
+
+// From `GL_AMD_pinned_memory`. The memory must be page-aligned.
+const EXTERNAL_VIRTUAL_MEMORY_BUFFER_AMD: GLenum = 0x9160;
+const PINNED_MEMORY_ALIGNMENT: usize = 4096;
+
+// From `GL_NVX_gpu_memory_info` and `GL_ATI_meminfo`. Both report kilobytes.
+const GPU_MEMORY_INFO_TOTAL_AVAILABLE_MEMORY_NVX: GLenum = 0x9048;
+const GPU_MEMORY_INFO_CURRENT_AVAILABLE_VIDMEM_NVX: GLenum = 0x9049;
+const TEXTURE_FREE_MEMORY_ATI: GLenum = 0x87fc;
 
 pub static DEVICE_FUNCTIONS: DeviceFunctions = DeviceFunctions {
     destroy: destroy,
     create_queue: create_queue,
     create_program: create_program,
     create_buffer: create_buffer,
+    create_host_buffer: create_host_buffer,
     create_image: create_image,
+    create_user_event: create_user_event,
+    calibrate_clock: calibrate_clock,
+    buffer_offset_alignment: buffer_offset_alignment,
+    create_sampler: create_sampler,
+    driver_memory_info: driver_memory_info,
+    import_buffer: import_buffer,
+    import_image: import_image,
+    supports_image_format: supports_image_format,
 };
 
-unsafe fn destroy(_: &Device) {}
+// The extensions of the OpenGL context that was current when the device was opened, looked up
+// once since listing them takes a call per extension.
+struct GlDevice {
+    extensions: HashSet<String>,
+}
+
+/// Creates a device for the current OpenGL context.
+pub unsafe fn create() -> Device {
+    let mut extension_count = 0;
+    gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extension_count);
+    let extensions = (0..extension_count as GLuint).filter_map(|index| {
+        let extension = gl::GetStringi(gl::EXTENSIONS, index);
+        if extension.is_null() {
+            return None
+        }
+        Some(CStr::from_ptr(extension as *const c_char).to_string_lossy().into_owned())
+    }).collect();
 
-fn create_queue(_: &Device) -> Result<Queue, Error> {
+    let device = Box::new(GlDevice {
+        extensions: extensions,
+    });
+    Device::from_raw_data(Box::into_raw(device) as usize, &DEVICE_FUNCTIONS)
+}
+
+fn gl_device(device: &Device) -> &GlDevice {
     unsafe {
-        Ok(Queue::from_raw_data(0, &QUEUE_FUNCTIONS))
+        &*(device.data() as *const GlDevice)
     }
 }
 
+fn has_extension(device: &Device, name: &str) -> bool {
+    gl_device(device).extensions.contains(name)
+}
+
+unsafe fn destroy(this: &Device) {
+    drop(Box::from_raw(this.data() as *mut GlDevice))
+}
+
+// All queues share the single command stream of the current OpenGL context, so commands always
+// execute in order and `options.out_of_order` has no effect.
+fn create_queue(_: &Device, options: &QueueOptions) -> Result<Queue, Error> {
+    Ok(queue::create(options))
+}
+
 fn create_program(_: &Device, source: &str) -> Result<Program, Error> {
     unsafe {
         let shader = gl::CreateShader(gl::COMPUTE_SHADER);
@@ -84,28 +149,88 @@ fn create_program(_: &Device, source: &str) -> Result<Program, Error> {
     }
 }
 
-fn create_buffer(_: &Device, _: Protection, mut data: BufferData) -> Result<Buffer, Error> {
+fn create_buffer(_: &Device, protection: Protection, location: MemoryLocation, data: BufferData)
+                 -> Result<Buffer, Error> {
     unsafe {
+        let (size, contents) = match data {
+            BufferData::HostAllocated(ref host_buffer) => {
+                (host_buffer.size(), host_buffer.as_ptr() as *const c_void)
+            }
+            BufferData::Uninitialized(size) => (size, ptr::null()),
+        };
+
         let mut buffer = 0;
         gl::GenBuffers(1, &mut buffer);
         gl::BindBuffer(gl::COPY_WRITE_BUFFER, buffer);
 
-        match data {
-            BufferData::HostAllocated(ref mut host_buffer) => {
-                gl::BufferData(gl::COPY_WRITE_BUFFER,
-                               host_buffer.size() as isize,
-                               host_buffer.as_ptr() as *const c_void,
-                               gl::DYNAMIC_DRAW)
+        // Immutable storage lets us tell the driver exactly how the CPU will access host-visible
+        // buffers. Device-local buffers keep mutable storage so that they can still be mapped in
+        // any mode.
+        let storage_flags = match location {
+            MemoryLocation::DeviceLocal => None,
+            MemoryLocation::Upload => Some(gl::MAP_WRITE_BIT),
+            MemoryLocation::Readback => Some(gl::MAP_READ_BIT | gl::CLIENT_STORAGE_BIT),
+            MemoryLocation::Streaming | MemoryLocation::Host => {
+                Some(gl::MAP_READ_BIT | gl::MAP_WRITE_BIT | gl::CLIENT_STORAGE_BIT)
+            }
+        };
+
+        match storage_flags {
+            Some(flags) if gl::BufferStorage::is_loaded() => {
+                gl::BufferStorage(gl::COPY_WRITE_BUFFER, size as GLsizeiptr, contents, flags)
             }
-            BufferData::Uninitialized(size) => {
-                gl::BufferData(gl::COPY_WRITE_BUFFER, size as isize, ptr::null(), gl::DYNAMIC_DRAW)
+            _ => {
+                let usage = match (location, protection) {
+                    (MemoryLocation::DeviceLocal, Protection::Constant) => gl::STATIC_DRAW,
+                    (MemoryLocation::DeviceLocal, _) => gl::DYNAMIC_DRAW,
+                    (MemoryLocation::Upload, _) |
+                    (MemoryLocation::Streaming, _) |
+                    (MemoryLocation::Host, _) => gl::STREAM_DRAW,
+                    (MemoryLocation::Readback, _) => gl::STREAM_READ,
+                };
+                gl::BufferData(gl::COPY_WRITE_BUFFER, size as GLsizeiptr, contents, usage)
             }
         }
 
-        Ok(Buffer::from_raw_data(buffer as usize, &BUFFER_FUNCTIONS))
+        Ok(Buffer::from_raw_data(buffer as usize, size, protection, location, &BUFFER_FUNCTIONS))
     }
 }
 
+unsafe fn create_host_buffer(this: &Device, protection: Protection, data: *mut u8, size: usize)
+                             -> Result<Buffer, Error> {
+    if !has_extension(this, "GL_AMD_pinned_memory") {
+        return Err(Error::Unsupported("wrapping host memory without `GL_AMD_pinned_memory`"))
+    }
+    if data as usize % PINNED_MEMORY_ALIGNMENT != 0 {
+        return Err(Error::Misaligned)
+    }
+
+    // Clear errors left by earlier calls, so that only failures to wrap the memory are caught
+    // below.
+    while gl::GetError() != gl::NO_ERROR {}
+
+    let mut buffer = 0;
+    gl::GenBuffers(1, &mut buffer);
+    gl::BindBuffer(EXTERNAL_VIRTUAL_MEMORY_BUFFER_AMD, buffer);
+    gl::BufferData(EXTERNAL_VIRTUAL_MEMORY_BUFFER_AMD,
+                   size as GLsizeiptr,
+                   data as *const c_void,
+                   gl::STREAM_READ);
+    gl::BindBuffer(EXTERNAL_VIRTUAL_MEMORY_BUFFER_AMD, 0);
+
+    if gl::GetError() != gl::NO_ERROR {
+        let mut buffer = buffer;
+        gl::DeleteBuffers(1, &mut buffer);
+        return Err(Error::Failed)
+    }
+
+    Ok(Buffer::from_raw_data(buffer as usize,
+                             size,
+                             protection,
+                             MemoryLocation::Host,
+                             &HOST_BUFFER_FUNCTIONS))
+}
+
 fn create_image(_: &Device, format: Format, protection: Protection, size: &Size2D<u32>)
                 -> Result<Image, Error> {
     unsafe {
@@ -124,3 +249,159 @@ fn create_image(_: &Device, format: Format, protection: Protection, size: &Size2
     }
 }
 
+fn create_user_event(_: &Device) -> Result<SyncEvent, Error> {
+    Ok(sync_event::create_user_event())
+}
+
+// `glGetInteger64v(GL_TIMESTAMP)` returns the GPU time once all previous commands have reached
+// the GPU, so take the host time on either side of it and use the midpoint.
+fn calibrate_clock(_: &Device) -> Result<ClockCalibration, Error> {
+    let before = Instant::now();
+    let gpu_timestamp = profile_event::current_timestamp();
+    let after = Instant::now();
+    Ok(ClockCalibration {
+        gpu_timestamp: gpu_timestamp,
+        host_time: before + (after - before) / 2,
+    })
+}
+
+fn buffer_offset_alignment(_: &Device) -> Result<usize, Error> {
+    Ok(queue::storage_buffer_offset_alignment())
+}
+
+fn create_sampler(_: &Device, filter: Filter, address_mode: AddressMode, normalized_coords: bool)
+                  -> Result<Sampler, Error> {
+    // Rectangle textures are always addressed in texels.
+    if normalized_coords {
+        return Err(Error::Unsupported("normalized coordinates on rectangle textures"))
+    }
+
+    // Images are rectangle textures, which only support clamping.
+    let wrap = match address_mode {
+        AddressMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+        AddressMode::ClampToBorder => gl::CLAMP_TO_BORDER,
+        AddressMode::Repeat | AddressMode::MirroredRepeat => {
+            return Err(Error::Unsupported("repeating address modes on rectangle textures"))
+        }
+    };
+    let filter = match filter {
+        Filter::Nearest => gl::NEAREST,
+        Filter::Linear => gl::LINEAR,
+    };
+
+    unsafe {
+        let mut sampler = 0;
+        gl::GenSamplers(1, &mut sampler);
+        gl::SamplerParameteri(sampler, gl::TEXTURE_MIN_FILTER, filter as GLint);
+        gl::SamplerParameteri(sampler, gl::TEXTURE_MAG_FILTER, filter as GLint);
+        gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_S, wrap as GLint);
+        gl::SamplerParameteri(sampler, gl::TEXTURE_WRAP_T, wrap as GLint);
+        Ok(Sampler::from_raw_data(sampler as usize, &SAMPLER_FUNCTIONS))
+    }
+}
+
+fn driver_memory_info(this: &Device) -> Result<Option<DriverMemoryInfo>, Error> {
+    unsafe {
+        if has_extension(this, "GL_NVX_gpu_memory_info") {
+            let (mut total, mut available) = (0, 0);
+            gl::GetIntegerv(GPU_MEMORY_INFO_TOTAL_AVAILABLE_MEMORY_NVX, &mut total);
+            gl::GetIntegerv(GPU_MEMORY_INFO_CURRENT_AVAILABLE_VIDMEM_NVX, &mut available);
+            return Ok(Some(DriverMemoryInfo {
+                total_bytes: Some(total as u64 * 1024),
+                available_bytes: Some(available as u64 * 1024),
+            }))
+        }
+
+        if has_extension(this, "GL_ATI_meminfo") {
+            // The first of the four values is the total free memory in the pool.
+            let mut info = [0; 4];
+            gl::GetIntegerv(TEXTURE_FREE_MEMORY_ATI, info.as_mut_ptr());
+            return Ok(Some(DriverMemoryInfo {
+                total_bytes: None,
+                available_bytes: Some(info[0] as u64 * 1024),
+            }))
+        }
+
+        Ok(None)
+    }
+}
+
+fn import_buffer(_: &Device, external_buffer: &ExternalBuffer, protection: Protection)
+                 -> Result<Buffer, Error> {
+    unsafe {
+        match *external_buffer {
+            ExternalBuffer::GlBuffer(buffer) => {
+                if gl::IsBuffer(buffer) != gl::TRUE {
+                    return Err(Error::Failed)
+                }
+                Ok(Buffer::from_raw_data(buffer as usize,
+                                         gl_buffer::gl_buffer_size(buffer),
+                                         protection,
+                                         MemoryLocation::DeviceLocal,
+                                         &IMPORTED_BUFFER_FUNCTIONS))
+            }
+        }
+    }
+}
+
+fn import_image(_: &Device, external_image: &ExternalImage, protection: Protection)
+                -> Result<Image, Error> {
+    unsafe {
+        match *external_image {
+            ExternalImage::GlTexture(texture) => {
+                if gl::IsTexture(texture) != gl::TRUE {
+                    return Err(Error::Failed)
+                }
+                if !try!(gl_image::is_rectangle_texture(texture)) {
+                    return Err(Error::Unsupported("importing textures other than rectangle ones"))
+                }
+                let image = Image::from_raw_data([texture as usize, protection as usize],
+                                                 &IMPORTED_IMAGE_FUNCTIONS);
+                if image.format().is_err() {
+                    return Err(Error::Unsupported("importing textures of this format"))
+                }
+                Ok(image)
+            }
+        }
+    }
+}
+
+// Without `GL_ARB_internalformat_query2`, assume that the driver supports all the formats, which
+// core OpenGL requires for textures. `Uniform::Image` binds images for image load and store, with
+// the image's own format, which some formats don't support. sRGB images can only be sampled, so
+// they need nothing more.
+fn supports_image_format(this: &Device, format: Format, protection: Protection)
+                         -> Result<bool, Error> {
+    unsafe {
+        if !has_extension(this, "GL_ARB_internalformat_query2") {
+            return Ok(true)
+        }
+
+        let internal_format = format.gl_internal_format();
+        let mut supported = 0;
+        gl::GetInternalformativ(gl::TEXTURE_RECTANGLE,
+                                internal_format,
+                                gl::INTERNALFORMAT_SUPPORTED,
+                                1,
+                                &mut supported);
+        if supported != gl::TRUE as GLint {
+            return Ok(false)
+        }
+
+        let parameters: &[GLenum] = match protection {
+            Protection::ReadOnly | Protection::Constant if format == Format::SRGBA8 => &[],
+            Protection::ReadOnly | Protection::Constant => &[gl::SHADER_IMAGE_LOAD],
+            Protection::WriteOnly => &[gl::SHADER_IMAGE_STORE],
+            Protection::ReadWrite => &[gl::SHADER_IMAGE_LOAD, gl::SHADER_IMAGE_STORE],
+        };
+        Ok(parameters.iter().all(|&parameter| {
+            let mut support = 0;
+            gl::GetInternalformativ(gl::TEXTURE_RECTANGLE,
+                                    internal_format,
+                                    parameter,
+                                    1,
+                                    &mut support);
+            support as GLenum != gl::NONE
+        }))
+    }
+}
@@ -9,7 +9,7 @@
 // except according to those terms.
 
 use error::Error;
-use gl::types::GLuint;
+use gl::types::{GLenum, GLint, GLuint};
 use gl;
 use image::{ExternalImage, Format, Image, ImageFunctions};
 
@@ -21,22 +21,28 @@ pub static IMAGE_FUNCTIONS: ImageFunctions = ImageFunctions {
     format: format,
 };
 
+// Images that wrap textures owned by the caller.
+pub static IMPORTED_IMAGE_FUNCTIONS: ImageFunctions = ImageFunctions {
+    destroy: destroy_imported,
+    bind_to: bind_to,
+    width: width,
+    height: height,
+    format: format,
+};
+
 unsafe fn destroy(this: &Image) {
     let mut texture = this.data()[0] as GLuint;
     gl::DeleteTextures(1, &mut texture);
 }
 
+unsafe fn destroy_imported(_: &Image) {}
+
 fn bind_to(this: &Image, external_texture: &ExternalImage) -> Result<(), Error> {
     unsafe {
         match *external_texture {
             ExternalImage::GlTexture(texture) => {
-                let mut format = 0;
-                gl::ActiveTexture(gl::TEXTURE0);
-                gl::BindTexture(gl::TEXTURE_RECTANGLE, this.data()[0] as GLuint);
-                gl::GetTexLevelParameteriv(gl::TEXTURE_RECTANGLE,
-                                           0,
-                                           gl::TEXTURE_INTERNAL_FORMAT,
-                                           &mut format);
+                let format = level_parameter(this.data()[0] as GLuint,
+                                             gl::TEXTURE_INTERNAL_FORMAT);
 
                 gl::TextureView(texture,
                                 gl::TEXTURE_RECTANGLE,
@@ -54,41 +60,64 @@ fn bind_to(this: &Image, external_texture: &ExternalImage) -> Result<(), Error>
 
 fn width(this: &Image) -> Result<u32, Error> {
     unsafe {
-        let mut width = 0;
-        gl::ActiveTexture(gl::TEXTURE0);
-        gl::BindTexture(gl::TEXTURE_RECTANGLE, this.data()[0] as GLuint);
-        gl::GetTexLevelParameteriv(gl::TEXTURE_RECTANGLE, 0, gl::TEXTURE_WIDTH, &mut width);
-        Ok(width as u32)
+        Ok(level_parameter(this.data()[0] as GLuint, gl::TEXTURE_WIDTH) as u32)
     }
 }
 
 fn height(this: &Image) -> Result<u32, Error> {
     unsafe {
-        let mut height = 0;
-        gl::ActiveTexture(gl::TEXTURE0);
-        gl::BindTexture(gl::TEXTURE_RECTANGLE, this.data()[0] as GLuint);
-        gl::GetTexLevelParameteriv(gl::TEXTURE_RECTANGLE, 0, gl::TEXTURE_HEIGHT, &mut height);
-        Ok(height as u32)
+        Ok(level_parameter(this.data()[0] as GLuint, gl::TEXTURE_HEIGHT) as u32)
     }
 }
 
 fn format(this: &Image) -> Result<Format, Error> {
     unsafe {
-        let mut internal_format = 0;
-        gl::ActiveTexture(gl::TEXTURE0);
-        gl::BindTexture(gl::TEXTURE_RECTANGLE, this.data()[0] as GLuint);
-        gl::GetTexLevelParameteriv(gl::TEXTURE_RECTANGLE,
-                                   0,
-                                   gl::TEXTURE_INTERNAL_FORMAT,
-                                   &mut internal_format);
-
-        // This must match the definition of `Format::gl_internal_format()`.
-        match internal_format as GLuint {
-            gl::R8 => Ok(Format::R8),
-            gl::RGBA8 => Ok(Format::RGBA8),
-            gl::R32F => Ok(Format::R32F),
-            _ => Err(Error::Failed),
-        }
+        let internal_format = level_parameter(this.data()[0] as GLuint,
+                                              gl::TEXTURE_INTERNAL_FORMAT);
+        Format::from_gl_internal_format(internal_format as GLuint).ok_or(Error::Failed)
+    }
+}
+
+/// Returns true if `texture` names a rectangle texture.
+///
+/// This leaves the caller's texture bindings as they were. With direct state access the target is
+/// queried directly. Without it, the only way to learn the target is to try binding the texture
+/// and see whether that raises an error, which can't be told apart from errors raised earlier.
+/// Those can't be put back once read, so rather than swallowing them this fails if there are any.
+pub unsafe fn is_rectangle_texture(texture: GLuint) -> Result<bool, Error> {
+    if gl::IsTexture(texture) != gl::TRUE {
+        return Ok(false)
     }
+
+    if gl::GetTextureParameteriv::is_loaded() {
+        let mut target = 0;
+        gl::GetTextureParameteriv(texture, gl::TEXTURE_TARGET, &mut target);
+        return Ok(target as GLenum == gl::TEXTURE_RECTANGLE)
+    }
+
+    if gl::GetError() != gl::NO_ERROR {
+        return Err(Error::Failed)
+    }
+    let mut previous_texture = 0;
+    gl::GetIntegerv(gl::TEXTURE_BINDING_RECTANGLE, &mut previous_texture);
+    gl::BindTexture(gl::TEXTURE_RECTANGLE, texture);
+    let is_rectangle = gl::GetError() == gl::NO_ERROR;
+    gl::BindTexture(gl::TEXTURE_RECTANGLE, previous_texture as GLuint);
+    Ok(is_rectangle)
 }
 
+/// Returns a parameter of level 0 of the rectangle texture `texture`.
+///
+/// The texture is bound to the active unit only for the query, and the caller's binding is
+/// restored afterward.
+pub unsafe fn level_parameter(texture: GLuint, parameter: GLenum) -> GLint {
+    let mut previous_texture = 0;
+    gl::GetIntegerv(gl::TEXTURE_BINDING_RECTANGLE, &mut previous_texture);
+
+    let mut value = 0;
+    gl::BindTexture(gl::TEXTURE_RECTANGLE, texture);
+    gl::GetTexLevelParameteriv(gl::TEXTURE_RECTANGLE, 0, parameter, &mut value);
+
+    gl::BindTexture(gl::TEXTURE_RECTANGLE, previous_texture as GLuint);
+    value
+}
@@ -8,7 +8,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::gl::device::DEVICE_FUNCTIONS;
+use api::gl::device;
 use device::Device;
 use error::Error;
 use instance::{Instance, InstanceFunctions, ShadingLanguage};
@@ -17,6 +17,7 @@ pub static INSTANCE_FUNCTIONS: InstanceFunctions = InstanceFunctions {
     destroy: destroy,
     shading_language: shading_language,
     open_device: open_device,
+    open_device_with_gl_sharing: open_device,
 };
 
 pub fn create() -> Result<Instance, Error> {
@@ -33,7 +34,7 @@ fn shading_language(_: &Instance) -> ShadingLanguage {
 
 fn open_device(_: &Instance) -> Result<Device, Error> {
     unsafe {
-        Ok(Device::from_raw_data(0, &DEVICE_FUNCTIONS))
+        Ok(device::create())
     }
 }
 
@@ -8,26 +8,177 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use api::gl::reactor::{self, GlObject};
 use error::Error;
-use gl::types::GLuint;
+use gl::types::{GLint64, GLuint};
 use gl;
-use profile_event::{ProfileEvent, ProfileEventFunctions};
+use profile_event::{ProfileEvent, ProfileEventFunctions, Timestamps};
+use std::cell::{Cell, RefCell};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+// The maximum number of idle query objects to keep around for reuse.
+const MAX_POOLED_QUERIES: usize = 256;
+
+// How often to resynchronize the estimate of the GPU clock used for queue times.
+const CALIBRATION_INTERVAL: Duration = Duration::from_secs(1);
+
+thread_local! {
+    // A GPU timestamp and the host time at which it was taken, if any.
+    static CALIBRATION: Cell<Option<(u64, Instant)>> = Cell::new(None);
+
+    // Query objects belong to the OpenGL context, which is bound to a single thread, so the pool
+    // is per-thread.
+    static QUERY_POOL: RefCell<Vec<GLuint>> = RefCell::new(vec![]);
+}
 
 pub static PROFILE_EVENT_FUNCTIONS: ProfileEventFunctions = ProfileEventFunctions {
     destroy: destroy,
     time_elapsed: time_elapsed,
+    timestamps: timestamps,
+    poll_time_elapsed: poll_time_elapsed,
+    cancel_poll_time_elapsed: cancel_poll_time_elapsed,
 };
 
+/// The data behind an OpenGL profile event.
+pub struct GlProfileEvent {
+    /// The GPU time at which the command was submitted, estimated from the host clock.
+    queued: u64,
+    /// `GL_TIMESTAMP` queries issued before and after the command.
+    queries: [GLuint; 2],
+}
+
+/// Starts timing a command. Call `end()` after submitting the command.
+pub fn begin() -> Box<GlProfileEvent> {
+    unsafe {
+        let event = Box::new(GlProfileEvent {
+            queued: queued_timestamp(),
+            queries: [allocate_query(), allocate_query()],
+        });
+        gl::QueryCounter(event.queries[0], gl::TIMESTAMP);
+        event
+    }
+}
+
+/// Finishes timing a command started with `begin()`.
+pub fn end(event: Box<GlProfileEvent>) -> ProfileEvent {
+    unsafe {
+        gl::QueryCounter(event.queries[1], gl::TIMESTAMP);
+        ProfileEvent::from_raw_data(Box::into_raw(event) as usize, &PROFILE_EVENT_FUNCTIONS)
+    }
+}
+
+// Estimates the current GPU time from the host clock. Reading `GL_TIMESTAMP` waits for the GPU
+// to catch up with all previously submitted commands, so do that only to recalibrate, once per
+// `CALIBRATION_INTERVAL`.
+fn queued_timestamp() -> u64 {
+    CALIBRATION.with(|calibration| {
+        let now = Instant::now();
+        let (gpu_timestamp, host_time) = match calibration.get() {
+            Some((gpu_timestamp, host_time)) if now - host_time < CALIBRATION_INTERVAL => {
+                (gpu_timestamp, host_time)
+            }
+            _ => {
+                let gpu_timestamp = current_timestamp();
+                calibration.set(Some((gpu_timestamp, now)));
+                (gpu_timestamp, now)
+            }
+        };
+        let elapsed = now - host_time;
+        gpu_timestamp + elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+    })
+}
+
+/// Returns the current time on the GPU clock, in nanoseconds.
+pub fn current_timestamp() -> u64 {
+    unsafe {
+        let mut timestamp: GLint64 = 0;
+        gl::GetInteger64v(gl::TIMESTAMP, &mut timestamp);
+        timestamp as u64
+    }
+}
+
+fn allocate_query() -> GLuint {
+    QUERY_POOL.with(|pool| {
+        match pool.borrow_mut().pop() {
+            Some(query) => query,
+            None => {
+                let mut query = 0;
+                unsafe {
+                    gl::GenQueries(1, &mut query);
+                }
+                query
+            }
+        }
+    })
+}
+
+fn free_query(query: GLuint) {
+    QUERY_POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.len() < MAX_POOLED_QUERIES {
+            pool.push(query)
+        } else {
+            unsafe {
+                gl::DeleteQueries(1, &query)
+            }
+        }
+    })
+}
+
+fn gl_profile_event(event: &ProfileEvent) -> &GlProfileEvent {
+    unsafe {
+        &*(event.data() as *const GlProfileEvent)
+    }
+}
+
 unsafe fn destroy(event: &ProfileEvent) {
-    let mut data = event.data() as GLuint;
-    gl::DeleteQueries(1, &mut data);
+    let event = Box::from_raw(event.data() as *mut GlProfileEvent);
+    reactor::forget(GlObject::Query(event.queries[1]));
+    free_query(event.queries[0]);
+    free_query(event.queries[1]);
 }
 
 fn time_elapsed(event: &ProfileEvent) -> Result<u64, Error> {
+    let timestamps = try!(timestamps(event));
+    Ok(timestamps.execution_time())
+}
+
+fn timestamps(event: &ProfileEvent) -> Result<Timestamps, Error> {
+    let event = gl_profile_event(event);
+
     unsafe {
-        let mut result = 0;
-        gl::GetQueryObjectui64v(event.data() as GLuint, gl::QUERY_RESULT, &mut result);
-        Ok(result)
+        let (mut start, mut end) = (0, 0);
+        gl::GetQueryObjectui64v(event.queries[0], gl::QUERY_RESULT, &mut start);
+        gl::GetQueryObjectui64v(event.queries[1], gl::QUERY_RESULT, &mut end);
+        Ok(Timestamps {
+            queued: event.queued,
+            submitted: event.queued,
+            start: start,
+            end: end,
+        })
     }
 }
 
+fn poll_time_elapsed(event: &ProfileEvent, waker: &Waker) -> Poll<Result<u64, Error>> {
+    let gl_event = gl_profile_event(event);
+
+    reactor::poll_pending();
+
+    // The second query is issued last, so it's the last to become available.
+    let query = GlObject::Query(gl_event.queries[1]);
+    if !query.is_ready() {
+        unsafe {
+            // Make sure the query actually gets submitted, or it will never become available.
+            gl::Flush();
+        }
+        reactor::register(query, waker);
+        return Poll::Pending
+    }
+
+    Poll::Ready(time_elapsed(event))
+}
+
+fn cancel_poll_time_elapsed(event: &ProfileEvent) {
+    reactor::forget(GlObject::Query(gl_profile_event(event).queries[1]))
+}
@@ -8,12 +8,18 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use gl::types::GLuint;
+use api::gl::queue as gl_queue;
+use error::Error;
+use gl::types::{GLint, GLuint};
 use gl;
 use program::{Program, ProgramFunctions};
+use queue::Uniform;
+use std::ffi::CString;
+use std::ptr;
 
 pub static PROGRAM_FUNCTIONS: ProgramFunctions = ProgramFunctions {
     destroy: destroy,
+    uniform_index: uniform_index,
 };
 
 unsafe fn destroy(this: &Program) {
@@ -24,3 +30,64 @@ unsafe fn destroy(this: &Program) {
     gl::DeleteShader(shader);
 }
 
+fn uniform_index(this: &Program, name: &str, uniform: &Uniform) -> Result<u32, Error> {
+    let not_found = || Error::UniformNotFound(name.to_owned());
+    let c_name = try!(CString::new(name).map_err(|_| not_found()));
+    let program = this.data() as GLuint;
+
+    unsafe {
+        match *uniform {
+            Uniform::Buffer(_) | Uniform::BufferSlice(_) => {
+                let index = gl::GetProgramResourceIndex(program,
+                                                        gl::SHADER_STORAGE_BLOCK,
+                                                        c_name.as_ptr());
+                if index == gl::INVALID_INDEX {
+                    return Err(not_found())
+                }
+
+                let mut binding = 0;
+                gl::GetProgramResourceiv(program,
+                                         gl::SHADER_STORAGE_BLOCK,
+                                         index,
+                                         1,
+                                         &gl::BUFFER_BINDING,
+                                         1,
+                                         ptr::null_mut(),
+                                         &mut binding);
+                Ok(binding as u32)
+            }
+            Uniform::ConstantBuffer(_) | Uniform::Bytes(_) => {
+                let index = gl::GetUniformBlockIndex(program, c_name.as_ptr());
+                if index == gl::INVALID_INDEX {
+                    return Err(not_found())
+                }
+
+                let mut binding = 0;
+                gl::GetActiveUniformBlockiv(program,
+                                            index,
+                                            gl::UNIFORM_BLOCK_BINDING,
+                                            &mut binding);
+                Ok(binding as u32)
+            }
+            Uniform::Image(_) | Uniform::SampledImage(..) => {
+                let location = gl::GetUniformLocation(program, c_name.as_ptr());
+                if location < 0 {
+                    return Err(not_found())
+                }
+
+                // The value of an image or sampler uniform is the unit it is bound to.
+                let mut unit: GLint = 0;
+                gl::GetUniformiv(program, location, &mut unit);
+                Ok(unit as u32)
+            }
+            Uniform::LocalMemory(_) => Err(gl_queue::local_memory_unsupported()),
+            _ => {
+                let location = gl::GetUniformLocation(program, c_name.as_ptr());
+                if location < 0 {
+                    return Err(not_found())
+                }
+                Ok(location as u32)
+            }
+        }
+    }
+}
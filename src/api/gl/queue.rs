@@ -8,16 +8,19 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use api::gl::profile_event::PROFILE_EVENT_FUNCTIONS;
-use api::gl::sync_event::SYNC_EVENT_FUNCTIONS;
+use api::gl::image as gl_image;
+use api::gl::profile_event::{self, GlProfileEvent};
+use api::gl::sync_event;
 use buffer::{Buffer, Protection};
 use error::Error;
-use gl::types::{GLint, GLuint};
+use gl::types::{GLbitfield, GLenum, GLfloat, GLint, GLintptr, GLsizeiptr, GLuint};
 use gl;
-use image::{Color, Image};
+use image::{Color, Format, Image};
 use profile_event::ProfileEvent;
 use program::Program;
-use queue::{Queue, QueueFunctions, Uniform};
+use queue::{BarrierScope, Queue, QueueFunctions, QueueOptions, Uniform};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::os::raw::c_void;
 use sync_event::SyncEvent;
 
@@ -29,9 +32,102 @@ pub static QUEUE_FUNCTIONS: QueueFunctions = QueueFunctions {
     submit_clear: submit_clear,
     submit_read_buffer: submit_read_buffer,
     submit_sync_event: submit_sync_event,
+    submit_barrier: submit_barrier,
 };
 
-unsafe fn destroy(_: &Queue) {}
+const BUFFER_BARRIER_BITS: GLbitfield = gl::SHADER_STORAGE_BARRIER_BIT |
+    gl::UNIFORM_BARRIER_BIT | gl::BUFFER_UPDATE_BARRIER_BIT |
+    gl::CLIENT_MAPPED_BUFFER_BARRIER_BIT;
+const TEXTURE_BARRIER_BITS: GLbitfield = gl::SHADER_IMAGE_ACCESS_BARRIER_BIT |
+    gl::TEXTURE_FETCH_BARRIER_BIT | gl::TEXTURE_UPDATE_BARRIER_BIT;
+
+// Shader writes that no barrier has made visible yet. All queues share the command stream of the
+// current context, so this is tracked per thread rather than per queue.
+thread_local! {
+    static PENDING_WRITES: RefCell<PendingWrites> = RefCell::new(PendingWrites {
+        buffers: HashMap::new(),
+        textures: HashMap::new(),
+    });
+}
+
+// The maximum number of idle uniform buffers for `Uniform::Bytes` to keep around for reuse.
+const MAX_POOLED_UNIFORM_BUFFERS: usize = 64;
+
+thread_local! {
+    // Buffer names belong to the OpenGL context, which is bound to a single thread, so the pool is
+    // per-thread.
+    static UNIFORM_BUFFER_POOL: RefCell<Vec<GLuint>> = RefCell::new(vec![]);
+}
+
+// The temporary uniform buffers holding `Uniform::Bytes` data for one dispatch. These go back to
+// the pool when this is dropped, including when submission fails partway. Respecifying a store
+// with `glBufferData()` orphans the old one, so a buffer can be reused as soon as the dispatch
+// that read it has been submitted.
+struct UniformBuffers(Vec<GLuint>);
+
+impl UniformBuffers {
+    unsafe fn upload(&mut self, bytes: &[u8]) -> GLuint {
+        let buffer = UNIFORM_BUFFER_POOL.with(|pool| pool.borrow_mut().pop()).unwrap_or_else(|| {
+            let mut buffer = 0;
+            gl::GenBuffers(1, &mut buffer);
+            buffer
+        });
+        self.0.push(buffer);
+
+        gl::BindBuffer(gl::UNIFORM_BUFFER, buffer);
+        gl::BufferData(gl::UNIFORM_BUFFER,
+                       bytes.len() as GLsizeiptr,
+                       bytes.as_ptr() as *const c_void,
+                       gl::STREAM_DRAW);
+        buffer
+    }
+}
+
+impl Drop for UniformBuffers {
+    fn drop(&mut self) {
+        UNIFORM_BUFFER_POOL.with(|pool| {
+            let mut pool = pool.borrow_mut();
+            for &buffer in &self.0 {
+                if pool.len() < MAX_POOLED_UNIFORM_BUFFERS {
+                    pool.push(buffer)
+                } else {
+                    unsafe {
+                        gl::DeleteBuffers(1, &buffer)
+                    }
+                }
+            }
+        })
+    }
+}
+
+// For each buffer and texture written by a program, the barrier bits that have yet to be issued
+// before the writes are visible to every kind of access.
+struct PendingWrites {
+    buffers: HashMap<GLuint, GLbitfield>,
+    textures: HashMap<GLuint, GLbitfield>,
+}
+
+/// The data behind an OpenGL queue.
+struct GlQueue {
+    options: QueueOptions,
+    /// `GL_SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT`, cached so that binding a buffer slice needn't
+    /// ask the driver.
+    storage_buffer_offset_alignment: usize,
+}
+
+pub fn create(options: &QueueOptions) -> Queue {
+    let queue = Box::new(GlQueue {
+        options: *options,
+        storage_buffer_offset_alignment: storage_buffer_offset_alignment(),
+    });
+    unsafe {
+        Queue::from_raw_data(Box::into_raw(queue) as usize, &QUEUE_FUNCTIONS)
+    }
+}
+
+unsafe fn destroy(this: &Queue) {
+    drop(Box::from_raw(this.data() as *mut GlQueue))
+}
 
 fn flush(_: &Queue) -> Result<(), Error> {
     unsafe {
@@ -47,49 +143,97 @@ fn finish(_: &Queue) -> Result<(), Error> {
     }
 }
 
-fn submit_compute(_: &Queue,
+/// OpenGL has no way to size workgroup-local memory at dispatch time.
+pub fn local_memory_unsupported() -> Error {
+    Error::Unsupported("local memory arguments; declare a `shared` array in the shader instead")
+}
+
+fn submit_compute(this: &Queue,
                   program: &Program,
                   num_groups: &[u32],
                   uniforms: &[(u32, Uniform)],
-                  _: &[SyncEvent])
+                  events: &[SyncEvent])
                   -> Result<ProfileEvent, Error> {
     unsafe {
+        for &(_, ref uniform) in uniforms {
+            match *uniform {
+                Uniform::LocalMemory(_) => {
+                    return Err(local_memory_unsupported())
+                }
+                Uniform::BufferSlice(slice) => {
+                    if slice.offset() % gl_queue(this).storage_buffer_offset_alignment != 0 {
+                        return Err(Error::Misaligned)
+                    }
+                }
+                Uniform::Image(image) => {
+                    // Image load/store does not support sRGB formats.
+                    if try!(image.format()) == Format::SRGBA8 {
+                        return Err(Error::Unsupported("binding sRGB images for load and store"))
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        try!(wait_for_events(events));
+
+        if options(this).automatic_barriers {
+            let mut barrier_bits = 0;
+            for &(_, ref uniform) in uniforms {
+                match *uniform {
+                    Uniform::Buffer(buffer) => {
+                        barrier_bits |= pending_buffer_bits(buffer) & gl::SHADER_STORAGE_BARRIER_BIT
+                    }
+                    Uniform::BufferSlice(slice) => {
+                        barrier_bits |= pending_buffer_bits(slice.buffer()) &
+                            gl::SHADER_STORAGE_BARRIER_BIT
+                    }
+                    Uniform::ConstantBuffer(buffer) => {
+                        barrier_bits |= pending_buffer_bits(buffer) & gl::UNIFORM_BARRIER_BIT
+                    }
+                    Uniform::Image(image) => {
+                        barrier_bits |= pending_texture_bits(image) &
+                            gl::SHADER_IMAGE_ACCESS_BARRIER_BIT
+                    }
+                    Uniform::SampledImage(image, _) => {
+                        barrier_bits |= pending_texture_bits(image) & gl::TEXTURE_FETCH_BARRIER_BIT
+                    }
+                    _ => {}
+                }
+            }
+            memory_barrier(barrier_bits);
+        }
+
         gl::UseProgram(program.data() as GLuint);
 
+        let mut uniform_buffers = UniformBuffers(vec![]);
         for &(uniform_index, ref uniform) in uniforms {
             match *uniform {
                 Uniform::Buffer(buffer) => {
-                    gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
-
-                    let mut buffer_size = 0;
-                    gl::BindBuffer(gl::COPY_READ_BUFFER, buffer.data() as u32);
-                    gl::GetBufferParameteriv(gl::COPY_READ_BUFFER,
-                                             gl::BUFFER_SIZE,
-                                             &mut buffer_size);
-
                     gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER,
                                        uniform_index,
                                        buffer.data() as GLuint);
                 }
+                Uniform::ConstantBuffer(buffer) => {
+                    gl::BindBufferBase(gl::UNIFORM_BUFFER, uniform_index, buffer.data() as GLuint);
+                }
+                Uniform::BufferSlice(slice) => {
+                    gl::BindBufferRange(gl::SHADER_STORAGE_BUFFER,
+                                        uniform_index,
+                                        slice.buffer().data() as GLuint,
+                                        slice.offset() as GLintptr,
+                                        slice.len() as GLsizeiptr);
+                }
                 Uniform::Image(image) => {
-                    gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT |
-                                      gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
-
                     let access = match image.data()[1] {
-                        p if p == Protection::ReadOnly as usize => gl::READ_ONLY,
+                        p if p == Protection::ReadOnly as usize ||
+                            p == Protection::Constant as usize => gl::READ_ONLY,
                         p if p == Protection::WriteOnly as usize => gl::WRITE_ONLY,
                         _ => gl::READ_WRITE,
                     };
 
-                    let mut internal_format = 0;
-                    gl::ActiveTexture(gl::TEXTURE0);
-                    gl::BindTexture(gl::TEXTURE_RECTANGLE, image.data()[0] as GLuint);
-                    gl::GetTexLevelParameteriv(gl::TEXTURE_RECTANGLE,
-                                               0,
-                                               gl::TEXTURE_INTERNAL_FORMAT,
-                                               &mut internal_format);
-                    gl::BindTexture(gl::TEXTURE_RECTANGLE, 0);
-
+                    let internal_format = gl_image::level_parameter(image.data()[0] as GLuint,
+                                                                    gl::TEXTURE_INTERNAL_FORMAT);
                     gl::BindImageTexture(uniform_index,
                                          image.data()[0] as GLuint,
                                          0,
@@ -98,6 +242,14 @@ fn submit_compute(_: &Queue,
                                          access,
                                          internal_format as GLuint);
                 }
+                Uniform::SampledImage(image, sampler) => {
+                    let mut active_texture = 0;
+                    gl::GetIntegerv(gl::ACTIVE_TEXTURE, &mut active_texture);
+                    gl::ActiveTexture(gl::TEXTURE0 + uniform_index);
+                    gl::BindTexture(gl::TEXTURE_RECTANGLE, image.data()[0] as GLuint);
+                    gl::BindSampler(uniform_index, sampler.data() as GLuint);
+                    gl::ActiveTexture(active_texture as GLenum);
+                }
                 Uniform::U32(value) => gl::Uniform1ui(uniform_index as GLint, value),
                 Uniform::UVec4(values) => {
                     gl::Uniform4ui(uniform_index as GLint,
@@ -106,52 +258,135 @@ fn submit_compute(_: &Queue,
                                    values[2],
                                    values[3])
                 }
+                Uniform::I32(value) => gl::Uniform1i(uniform_index as GLint, value),
+                Uniform::IVec2(ref values) => {
+                    gl::Uniform2iv(uniform_index as GLint, 1, values.as_ptr())
+                }
+                Uniform::IVec3(ref values) => {
+                    gl::Uniform3iv(uniform_index as GLint, 1, values.as_ptr())
+                }
+                Uniform::IVec4(ref values) => {
+                    gl::Uniform4iv(uniform_index as GLint, 1, values.as_ptr())
+                }
+                Uniform::F32(value) => gl::Uniform1f(uniform_index as GLint, value),
+                Uniform::Vec2(ref values) => {
+                    gl::Uniform2fv(uniform_index as GLint, 1, values.as_ptr())
+                }
+                Uniform::Vec3(ref values) => {
+                    gl::Uniform3fv(uniform_index as GLint, 1, values.as_ptr())
+                }
+                Uniform::Vec4(ref values) => {
+                    gl::Uniform4fv(uniform_index as GLint, 1, values.as_ptr())
+                }
+                Uniform::Mat4(ref values) => {
+                    gl::UniformMatrix4fv(uniform_index as GLint,
+                                         1,
+                                         gl::FALSE,
+                                         values.as_ptr() as *const GLfloat)
+                }
+                Uniform::Bytes(bytes) => {
+                    let buffer = uniform_buffers.upload(bytes);
+                    gl::BindBufferBase(gl::UNIFORM_BUFFER, uniform_index, buffer);
+                }
+                Uniform::LocalMemory(_) => unreachable!(),
             }
         }
 
-        let mut query = 0;
-        gl::GenQueries(1, &mut query);
-        gl::BeginQuery(gl::TIME_ELAPSED, query);
+        let profile_event = begin_profiling(this);
 
         gl::DispatchCompute(*num_groups.get(0).unwrap_or(&1),
                             *num_groups.get(1).unwrap_or(&1),
                             *num_groups.get(2).unwrap_or(&1));
 
-        gl::EndQuery(gl::TIME_ELAPSED);
+        // We can't tell which buffers the program writes to, so assume all of them are.
+        PENDING_WRITES.with(|pending_writes| {
+            let mut pending_writes = pending_writes.borrow_mut();
+            for &(_, ref uniform) in uniforms {
+                match *uniform {
+                    Uniform::Buffer(buffer) => {
+                        pending_writes.buffers.insert(buffer.data() as GLuint,
+                                                      BUFFER_BARRIER_BITS);
+                    }
+                    // Track writes to slices as writes to the whole buffer. This may issue
+                    // unnecessary barriers, but never misses a needed one.
+                    Uniform::BufferSlice(slice) => {
+                        pending_writes.buffers.insert(slice.buffer().data() as GLuint,
+                                                      BUFFER_BARRIER_BITS);
+                    }
+                    Uniform::Image(image) if image.data()[1] != Protection::ReadOnly as usize &&
+                            image.data()[1] != Protection::Constant as usize => {
+                        pending_writes.textures.insert(image.data()[0] as GLuint,
+                                                       TEXTURE_BARRIER_BITS);
+                    }
+                    _ => {}
+                }
+            }
+        });
 
-        Ok(ProfileEvent::from_raw_data(query as usize, &PROFILE_EVENT_FUNCTIONS))
+        Ok(end_profiling(profile_event))
     }
 }
 
-fn submit_clear(_: &Queue, image: &Image, color: &Color, _: &[SyncEvent])
+fn submit_clear(this: &Queue, image: &Image, color: &Color, events: &[SyncEvent])
                 -> Result<ProfileEvent, Error> {
     unsafe {
+        try!(wait_for_events(events));
+
         let color = match *color {
-            Color::UInt(r, _, _, _) => r as u8,
+            Color::UInt(r, g, b, a) => [r, g, b, a],
         };
+        let format = try!(image.format());
 
-        let mut query = 0;
-        gl::GenQueries(1, &mut query);
-        gl::BeginQuery(gl::TIME_ELAPSED, query);
+        if options(this).automatic_barriers {
+            memory_barrier(pending_texture_bits(image) & gl::TEXTURE_UPDATE_BARRIER_BIT);
+        }
 
-        gl::ClearTexImage(image.data()[0] as GLuint,
-                          0,
-                          gl::RED,
-                          gl::UNSIGNED_BYTE,
-                          &color as *const u8 as *const c_void);
+        let profile_event = begin_profiling(this);
 
-        gl::EndQuery(gl::TIME_ELAPSED);
+        // Integer images must be cleared with integer data and floating-point images with
+        // floating-point data, so that the components are not clamped. Other images are cleared
+        // with unsigned normalized bytes.
+        let texture = image.data()[0] as GLuint;
+        if format.is_integer() {
+            gl::ClearTexImage(texture,
+                              0,
+                              gl::RGBA_INTEGER,
+                              gl::UNSIGNED_INT,
+                              color.as_ptr() as *const c_void);
+        } else if format.is_floating_point() {
+            let color = [color[0] as f32, color[1] as f32, color[2] as f32, color[3] as f32];
+            gl::ClearTexImage(texture,
+                              0,
+                              gl::RGBA,
+                              gl::FLOAT,
+                              color.as_ptr() as *const c_void);
+        } else {
+            let color = [color[0] as u8, color[1] as u8, color[2] as u8, color[3] as u8];
+            gl::ClearTexImage(texture,
+                              0,
+                              gl::RGBA,
+                              gl::UNSIGNED_BYTE,
+                              color.as_ptr() as *const c_void);
+        }
 
-        Ok(ProfileEvent::from_raw_data(query as usize, &PROFILE_EVENT_FUNCTIONS))
+        Ok(end_profiling(profile_event))
     }
 }
 
-fn submit_read_buffer(_: &Queue, dest: &mut [u8], buffer: &Buffer, start: usize, _: &[SyncEvent])
+fn submit_read_buffer(this: &Queue,
+                      dest: &mut [u8],
+                      buffer: &Buffer,
+                      start: usize,
+                      events: &[SyncEvent])
                       -> Result<ProfileEvent, Error> {
     unsafe {
-        let mut query = 0;
-        gl::GenQueries(1, &mut query);
-        gl::BeginQuery(gl::TIME_ELAPSED, query);
+        try!(wait_for_events(events));
+
+        if options(this).automatic_barriers {
+            memory_barrier(pending_buffer_bits(buffer) & gl::BUFFER_UPDATE_BARRIER_BIT);
+        }
+
+        let profile_event = begin_profiling(this);
 
         gl::BindBuffer(gl::COPY_READ_BUFFER, buffer.data() as GLuint);
         gl::GetBufferSubData(gl::COPY_READ_BUFFER,
@@ -159,16 +394,111 @@ fn submit_read_buffer(_: &Queue, dest: &mut [u8], buffer: &Buffer, start: usize,
                              dest.len() as isize,
                              dest.as_mut_ptr() as *mut c_void);
 
-        gl::EndQuery(gl::TIME_ELAPSED);
-
-        Ok(ProfileEvent::from_raw_data(query as usize, &PROFILE_EVENT_FUNCTIONS))
+        Ok(end_profiling(profile_event))
     }
 }
 
 fn submit_sync_event(_: &Queue) -> Result<SyncEvent, Error> {
+    Ok(sync_event::create_fence())
+}
+
+fn submit_barrier(_: &Queue, scope: BarrierScope) -> Result<(), Error> {
+    memory_barrier(match scope {
+        BarrierScope::Storage => gl::SHADER_STORAGE_BARRIER_BIT | gl::UNIFORM_BARRIER_BIT,
+        BarrierScope::Image => gl::SHADER_IMAGE_ACCESS_BARRIER_BIT | gl::TEXTURE_FETCH_BARRIER_BIT,
+        BarrierScope::Transfer => gl::BUFFER_UPDATE_BARRIER_BIT | gl::TEXTURE_UPDATE_BARRIER_BIT,
+        BarrierScope::HostRead => {
+            gl::BUFFER_UPDATE_BARRIER_BIT | gl::TEXTURE_UPDATE_BARRIER_BIT |
+                gl::CLIENT_MAPPED_BUFFER_BARRIER_BIT
+        }
+        BarrierScope::All => BUFFER_BARRIER_BITS | TEXTURE_BARRIER_BITS,
+    });
+    Ok(())
+}
+
+// Mapping and copying count as buffer updates, so program writes to the buffer need a barrier
+// first.
+pub fn prepare_to_update_buffer(this: &Queue, buffer: &Buffer) {
+    unsafe {
+        if options(this).automatic_barriers {
+            memory_barrier(pending_buffer_bits(buffer) & gl::BUFFER_UPDATE_BARRIER_BIT);
+        }
+    }
+}
+
+pub fn storage_buffer_offset_alignment() -> usize {
+    unsafe {
+        let mut alignment = 0;
+        gl::GetIntegerv(gl::SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT, &mut alignment);
+        alignment as usize
+    }
+}
+
+unsafe fn gl_queue(this: &Queue) -> &GlQueue {
+    &*(this.data() as *const GlQueue)
+}
+
+unsafe fn options(this: &Queue) -> &QueueOptions {
+    &gl_queue(this).options
+}
+
+// Makes the GPU wait for the given events before executing subsequent commands. This is what
+// orders commands with respect to events from other queues (or other contexts).
+fn wait_for_events(events: &[SyncEvent]) -> Result<(), Error> {
+    for event in events {
+        try!(sync_event::wait_on_gpu(event))
+    }
+    Ok(())
+}
+
+fn begin_profiling(this: &Queue) -> Option<Box<GlProfileEvent>> {
     unsafe {
-        let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
-        Ok(SyncEvent::from_raw_data(fence as usize, &SYNC_EVENT_FUNCTIONS))
+        if options(this).profiling {
+            Some(profile_event::begin())
+        } else {
+            None
+        }
+    }
+}
+
+fn end_profiling(event: Option<Box<GlProfileEvent>>) -> ProfileEvent {
+    match event {
+        Some(event) => profile_event::end(event),
+        None => ProfileEvent::null(),
     }
 }
 
+fn pending_buffer_bits(buffer: &Buffer) -> GLbitfield {
+    PENDING_WRITES.with(|pending_writes| {
+        *pending_writes.borrow().buffers.get(&(buffer.data() as GLuint)).unwrap_or(&0)
+    })
+}
+
+fn pending_texture_bits(image: &Image) -> GLbitfield {
+    PENDING_WRITES.with(|pending_writes| {
+        *pending_writes.borrow().textures.get(&(image.data()[0] as GLuint)).unwrap_or(&0)
+    })
+}
+
+// Issues a barrier for the given bits, if any, and records that writes no longer need them.
+fn memory_barrier(bits: GLbitfield) {
+    if bits == 0 {
+        return
+    }
+
+    unsafe {
+        gl::MemoryBarrier(bits);
+    }
+
+    PENDING_WRITES.with(|pending_writes| {
+        let mut pending_writes = pending_writes.borrow_mut();
+        for pending_bits in pending_writes.buffers.values_mut() {
+            *pending_bits &= !bits
+        }
+        for pending_bits in pending_writes.textures.values_mut() {
+            *pending_bits &= !bits
+        }
+        pending_writes.buffers.retain(|_, pending_bits| *pending_bits != 0);
+        pending_writes.textures.retain(|_, pending_bits| *pending_bits != 0);
+    })
+}
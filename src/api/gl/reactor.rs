@@ -0,0 +1,182 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A reactor that drives futures waiting on OpenGL fences and queries.
+//!
+//! OpenGL objects can only be queried on the thread that owns their context, so the reactor
+//! thread can't check them itself. Instead, each context thread keeps its own registry of the
+//! objects its tasks wait on, and the reactor thread periodically wakes every waiting task. When
+//! a task polls, on its context thread, it calls `poll_pending()`, which checks the objects
+//! registered on that thread and wakes exactly the tasks whose objects are ready. The reactor
+//! backs off while nothing becomes ready.
+
+use gl::types::{GLsync, GLuint};
+use gl;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, Once};
+use std::task::Waker;
+use std::thread;
+use std::time::Duration;
+
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(32);
+
+// The wakers of every waiting task on every context thread, keyed by registration ID, so that the
+// reactor thread can wake them.
+static STATE: Mutex<ReactorState> = Mutex::new(ReactorState {
+    wakers: BTreeMap::new(),
+    interval: MIN_POLL_INTERVAL,
+});
+static PENDING_CHANGED: Condvar = Condvar::new();
+static START_REACTOR_THREAD: Once = Once::new();
+static NEXT_REGISTRATION_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // The objects that tasks on this thread wait on. Object names are only meaningful within the
+    // context that is current on this thread.
+    static PENDING: RefCell<Vec<Pending>> = RefCell::new(vec![]);
+}
+
+struct ReactorState {
+    wakers: BTreeMap<usize, Waker>,
+    interval: Duration,
+}
+
+/// An OpenGL object that a task can wait on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GlObject {
+    /// A fence, which is ready once it is signaled.
+    Fence(usize),
+    /// A query, which is ready once its result is available.
+    Query(GLuint),
+}
+
+struct Pending {
+    object: GlObject,
+    id: usize,
+}
+
+/// Arranges for `waker` to be woken once `object` is ready.
+///
+/// Registering the same object again replaces its waker. Must be called on the thread that owns
+/// the OpenGL context.
+pub fn register(object: GlObject, waker: &Waker) {
+    START_REACTOR_THREAD.call_once(|| {
+        thread::spawn(run);
+    });
+
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let mut state = STATE.lock().unwrap();
+        if let Some(pending) = pending.iter().find(|pending| pending.object == object) {
+            let registered = state.wakers.entry(pending.id).or_insert_with(|| waker.clone());
+            if !registered.will_wake(waker) {
+                *registered = waker.clone()
+            }
+            return
+        }
+
+        let id = NEXT_REGISTRATION_ID.fetch_add(1, Ordering::Relaxed);
+        pending.push(Pending {
+            object: object,
+            id: id,
+        });
+        state.wakers.insert(id, waker.clone());
+        PENDING_CHANGED.notify_one();
+    })
+}
+
+/// Forgets any task on this thread waiting on `object`. Call this before deleting the object, or
+/// once the task no longer waits on it.
+pub fn forget(object: GlObject) {
+    PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let mut state = STATE.lock().unwrap();
+        pending.retain(|pending| {
+            if pending.object != object {
+                return true
+            }
+            state.wakers.remove(&pending.id);
+            false
+        })
+    })
+}
+
+/// Wakes the tasks on this thread whose objects are ready. Must be called on the thread that owns
+/// the OpenGL context.
+pub fn poll_pending() {
+    let ready: Vec<_> = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let (ready, still_pending): (Vec<_>, Vec<_>) =
+            mem::replace(&mut *pending, vec![]).into_iter()
+                                               .partition(|pending| pending.object.is_ready());
+        *pending = still_pending;
+
+        let mut state = STATE.lock().unwrap();
+        if !ready.is_empty() {
+            state.interval = MIN_POLL_INTERVAL
+        }
+        ready.iter().filter_map(|pending| state.wakers.remove(&pending.id)).collect()
+    });
+
+    for waker in ready {
+        waker.wake()
+    }
+}
+
+impl GlObject {
+    /// Returns true if the object is ready. Errors count as ready, so that the waiting task polls
+    /// and sees them.
+    pub fn is_ready(self) -> bool {
+        unsafe {
+            match self {
+                GlObject::Fence(fence) => {
+                    gl::ClientWaitSync(fence as GLsync, gl::SYNC_FLUSH_COMMANDS_BIT, 0) !=
+                        gl::TIMEOUT_EXPIRED
+                }
+                GlObject::Query(query) => {
+                    let mut available = 0;
+                    gl::GetQueryObjectuiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+                    available != gl::FALSE as GLuint
+                }
+            }
+        }
+    }
+}
+
+fn run() {
+    loop {
+        let interval = {
+            let mut state = STATE.lock().unwrap();
+            while state.wakers.is_empty() {
+                state.interval = MIN_POLL_INTERVAL;
+                state = PENDING_CHANGED.wait(state).unwrap();
+            }
+            state.interval
+        };
+
+        thread::sleep(interval);
+
+        // Wake every waiting task so that each context thread with tasks calls `poll_pending()`.
+        // Waking just one could pick a task on a thread that never polls again.
+        let wakers: Vec<_> = {
+            let mut state = STATE.lock().unwrap();
+            state.interval = cmp::min(state.interval * 2, MAX_POLL_INTERVAL);
+            state.wakers.values().cloned().collect()
+        };
+        for waker in wakers {
+            waker.wake()
+        }
+    }
+}
@@ -8,27 +8,244 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use api::gl::reactor::{self, GlObject};
 use error::Error;
 use gl::types::{GLsync, GLuint64};
 use gl;
-use sync_event::{SyncEvent, SyncEventFunctions};
+use std::cmp;
+use std::mem;
+use std::sync::{Condvar, Mutex};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+use sync_event::{Status, SyncEvent, SyncEventFunctions};
 
-const TIMEOUT: GLuint64 = 1_000_000_000_000;
+// The longest single wait we ask the driver for. `wait()` waits in slices of this length so that
+// an absurdly large timeout never reaches the driver.
+const MAX_TIMEOUT: GLuint64 = 1_000_000_000_000;
 
 pub static SYNC_EVENT_FUNCTIONS: SyncEventFunctions = SyncEventFunctions {
     destroy: destroy,
     wait: wait,
+    wait_timeout: wait_timeout,
+    status: status,
+    signal: signal,
+    poll: poll,
+    cancel_poll: cancel_poll,
 };
 
+/// The data behind an OpenGL sync event.
+pub enum GlSyncEvent {
+    /// A fence inserted into the OpenGL command stream.
+    Fence(GLsync),
+    /// An event signaled by the CPU, emulated on the CPU side since OpenGL has no equivalent.
+    User(UserEvent),
+}
+
+pub struct UserEvent {
+    state: Mutex<UserEventState>,
+    signaled: Condvar,
+}
+
+struct UserEventState {
+    signaled: bool,
+    wakers: Vec<Waker>,
+}
+
+pub fn create_fence() -> SyncEvent {
+    unsafe {
+        let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        from_gl_sync_event(GlSyncEvent::Fence(fence))
+    }
+}
+
+pub fn create_user_event() -> SyncEvent {
+    from_gl_sync_event(GlSyncEvent::User(UserEvent {
+        state: Mutex::new(UserEventState {
+            signaled: false,
+            wakers: vec![],
+        }),
+        signaled: Condvar::new(),
+    }))
+}
+
+fn from_gl_sync_event(event: GlSyncEvent) -> SyncEvent {
+    unsafe {
+        SyncEvent::from_raw_data(Box::into_raw(Box::new(event)) as usize, &SYNC_EVENT_FUNCTIONS)
+    }
+}
+
+fn gl_sync_event(event: &SyncEvent) -> &GlSyncEvent {
+    unsafe {
+        &*(event.data() as *const GlSyncEvent)
+    }
+}
+
+/// Makes the GPU wait for the given event before executing subsequent commands.
+///
+/// User events can't be waited on by the GPU, so this fails with `Error::Unsupported` for user
+/// events that have not been signaled yet.
+pub fn wait_on_gpu(event: &SyncEvent) -> Result<(), Error> {
+    match *gl_sync_event(event) {
+        GlSyncEvent::Fence(fence) => {
+            unsafe {
+                gl::WaitSync(fence, 0, gl::TIMEOUT_IGNORED);
+            }
+            Ok(())
+        }
+        GlSyncEvent::User(ref user_event) => {
+            if user_event.state.lock().unwrap().signaled {
+                Ok(())
+            } else {
+                Err(Error::Unsupported("waiting on unsignaled user events"))
+            }
+        }
+    }
+}
+
 unsafe fn destroy(event: &SyncEvent) {
-    let data = event.data() as GLsync;
-    gl::DeleteSync(data);
+    let event = Box::from_raw(event.data() as *mut GlSyncEvent);
+    if let GlSyncEvent::Fence(fence) = *event {
+        reactor::forget(GlObject::Fence(fence as usize));
+        gl::DeleteSync(fence);
+    }
 }
 
 fn wait(event: &SyncEvent) -> Result<(), Error> {
-    unsafe {
-        gl::ClientWaitSync(event.data() as GLsync, gl::SYNC_FLUSH_COMMANDS_BIT, TIMEOUT);
-        Ok(())
+    match *gl_sync_event(event) {
+        GlSyncEvent::Fence(fence) => {
+            loop {
+                match client_wait(fence, MAX_TIMEOUT) {
+                    Err(Error::Timeout) => {}
+                    result => return result,
+                }
+            }
+        }
+        GlSyncEvent::User(ref user_event) => {
+            let mut state = user_event.state.lock().unwrap();
+            while !state.signaled {
+                state = user_event.signaled.wait(state).unwrap();
+            }
+            Ok(())
+        }
+    }
+}
+
+fn wait_timeout(event: &SyncEvent, timeout: Duration) -> Result<(), Error> {
+    match *gl_sync_event(event) {
+        GlSyncEvent::Fence(fence) => {
+            let mut remaining = timeout.as_secs()
+                                       .saturating_mul(1_000_000_000)
+                                       .saturating_add(timeout.subsec_nanos() as u64);
+            loop {
+                let slice = cmp::min(remaining, MAX_TIMEOUT);
+                match client_wait(fence, slice) {
+                    Err(Error::Timeout) if remaining > slice => remaining -= slice,
+                    result => return result,
+                }
+            }
+        }
+        GlSyncEvent::User(ref user_event) => {
+            // A timeout too long to represent is as good as none.
+            let deadline = match Instant::now().checked_add(timeout) {
+                Some(deadline) => deadline,
+                None => return wait(event),
+            };
+            let mut state = user_event.state.lock().unwrap();
+            while !state.signaled {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::Timeout)
+                }
+                state = user_event.signaled.wait_timeout(state, deadline - now).unwrap().0;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn status(event: &SyncEvent) -> Result<Status, Error> {
+    match *gl_sync_event(event) {
+        GlSyncEvent::Fence(fence) => {
+            // OpenGL can't tell us whether the commands have started, so report them as running.
+            match client_wait(fence, 0) {
+                Ok(()) => Ok(Status::Complete),
+                Err(Error::Timeout) => Ok(Status::Running),
+                Err(err) => Err(err),
+            }
+        }
+        GlSyncEvent::User(ref user_event) => {
+            if user_event.state.lock().unwrap().signaled {
+                Ok(Status::Complete)
+            } else {
+                Ok(Status::Queued)
+            }
+        }
+    }
+}
+
+fn signal(event: &SyncEvent) -> Result<(), Error> {
+    match *gl_sync_event(event) {
+        GlSyncEvent::Fence(_) => Err(Error::Failed),
+        GlSyncEvent::User(ref user_event) => {
+            let wakers = {
+                let mut state = user_event.state.lock().unwrap();
+                if state.signaled {
+                    return Err(Error::Failed)
+                }
+                state.signaled = true;
+                mem::replace(&mut state.wakers, vec![])
+            };
+
+            user_event.signaled.notify_all();
+            for waker in wakers {
+                waker.wake()
+            }
+            Ok(())
+        }
+    }
+}
+
+fn poll(event: &SyncEvent, waker: &Waker) -> Poll<Result<(), Error>> {
+    match *gl_sync_event(event) {
+        GlSyncEvent::Fence(fence) => {
+            reactor::poll_pending();
+            match status(event) {
+                Ok(Status::Complete) => Poll::Ready(Ok(())),
+                Ok(_) => {
+                    reactor::register(GlObject::Fence(fence as usize), waker);
+                    Poll::Pending
+                }
+                Err(err) => Poll::Ready(Err(err)),
+            }
+        }
+        GlSyncEvent::User(ref user_event) => {
+            // User events are signaled from the CPU, so there's no need to involve the reactor.
+            let mut state = user_event.state.lock().unwrap();
+            if state.signaled {
+                return Poll::Ready(Ok(()))
+            }
+            if !state.wakers.iter().any(|other| other.will_wake(waker)) {
+                state.wakers.push(waker.clone())
+            }
+            Poll::Pending
+        }
     }
 }
 
+// A task that stops waiting on a fence must not stay registered with the reactor, or its waker
+// would keep being woken. User events keep their wakers until they are signaled.
+fn cancel_poll(event: &SyncEvent) {
+    if let GlSyncEvent::Fence(fence) = *gl_sync_event(event) {
+        reactor::forget(GlObject::Fence(fence as usize))
+    }
+}
+
+fn client_wait(fence: GLsync, timeout: GLuint64) -> Result<(), Error> {
+    unsafe {
+        match gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, timeout) {
+            gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => Ok(()),
+            gl::TIMEOUT_EXPIRED => Err(Error::Timeout),
+            _ => Err(Error::Failed),
+        }
+    }
+}
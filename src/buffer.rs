@@ -10,18 +10,41 @@
 
 //! Data buffers on the GPU.
 
+use error::Error;
+use memory::TrackedMemory;
+use queue::Queue;
+use std::marker::PhantomData;
 use std::mem;
+use std::ops::{Deref, DerefMut, Range};
 use std::slice;
+use sync_event::SyncEvent;
 
 /// A data buffer on the GPU.
 pub struct Buffer {
     data: usize,
+    size: usize,
+    protection: Protection,
+    location: MemoryLocation,
+    memory: Option<TrackedMemory>,
+    label: Option<String>,
     functions: &'static BufferFunctions,
 }
 
 #[doc(hidden)]
 pub struct BufferFunctions {
     pub destroy: unsafe extern "Rust" fn(this: &Buffer),
+    pub map: extern "Rust" fn(this: &Buffer, queue: &Queue, range: Range<usize>, mode: MapMode)
+                              -> Result<*mut u8, Error>,
+    pub unmap: unsafe extern "Rust" fn(this: &Buffer, queue: &Queue, ptr: *mut u8),
+    pub copy_to: extern "Rust" fn(this: &Buffer, queue: &Queue, external_buffer: &ExternalBuffer)
+                                  -> Result<(), Error>,
+}
+
+/// An external resource that the contents of a buffer can be copied to, or that can be imported
+/// as one.
+pub enum ExternalBuffer {
+    /// The name of an OpenGL buffer object.
+    GlBuffer(u32),
 }
 
 /// Memory protection from the GPU side. (The CPU is always free to perform whatever reads and
@@ -39,6 +62,71 @@ pub enum Protection {
     WriteOnly = 1,
     /// The GPU may read and write to the buffer.
     ReadWrite = 2,
+    /// The GPU may only read from this buffer, and it will be bound with
+    /// `Uniform::ConstantBuffer`.
+    ///
+    /// This lets the driver place the buffer in memory suited to small read-only parameter
+    /// blocks. Images treat this like `ReadOnly`.
+    Constant = 3,
+}
+
+/// Where the memory for a buffer should live, according to how the CPU and GPU will use it.
+///
+/// This is a hint: backends choose the closest placement they support.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemoryLocation {
+    /// Memory that the GPU accesses fastest. The CPU rarely touches it after creation.
+    DeviceLocal,
+    /// Memory that the CPU writes and the GPU reads, for uploading data.
+    ///
+    /// Buffers in this location can only be mapped with `Buffer::map_write()`.
+    Upload,
+    /// Memory that the GPU writes and the CPU reads, for reading back results.
+    ///
+    /// Buffers in this location can only be mapped with `Buffer::map()`.
+    Readback,
+    /// Memory that the CPU rewrites frequently and the GPU reads once or a few times per write.
+    Streaming,
+    /// Caller-owned memory wrapped by `Device::create_host_buffer()`.
+    ///
+    /// Passing this to `Device::create_buffer_in()` behaves like `Streaming`.
+    Host,
+}
+
+/// How the CPU will access a mapped buffer.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapMode {
+    /// The CPU will only read from the mapping (see `Buffer::map()`).
+    Read,
+    /// The CPU will only write to the mapping (see `Buffer::map_write()`).
+    ///
+    /// The previous contents of the mapped range are undefined, which spares the driver from
+    /// copying them to the CPU.
+    Write,
+    /// The CPU will read and write to the mapping (see `Buffer::map_mut()`).
+    ReadWrite,
+}
+
+/// A range of a buffer mapped into CPU memory for reading, which is unmapped when this is dropped.
+///
+/// The buffer must not be used by the GPU while it is mapped.
+pub struct MappedBuffer<'a> {
+    mapping: Mapping<'a>,
+}
+
+/// A range of a buffer mapped into CPU memory for writing, which is unmapped when this is dropped.
+///
+/// The buffer must not be used by the GPU while it is mapped.
+pub struct MappedBufferMut<'a> {
+    mapping: Mapping<'a>,
+    mode: MapMode,
+}
+
+struct Mapping<'a> {
+    buffer: &'a Buffer,
+    queue: &'a Queue,
+    ptr: *mut u8,
+    len: usize,
 }
 
 /// Where the initial data for a buffer comes from.
@@ -49,6 +137,50 @@ pub enum BufferData<'a> {
     HostAllocated(HostAllocatedData<'a>),
 }
 
+/// A view of a range of bytes within a buffer, which can be passed to a program in place of the
+/// whole buffer.
+#[derive(Clone, Copy)]
+pub struct BufferSlice<'a> {
+    buffer: &'a Buffer,
+    offset: usize,
+    len: usize,
+}
+
+/// A buffer of elements of type `T`, which knows how many elements it holds.
+pub struct TypedBuffer<T> where T: Pod {
+    buffer: Buffer,
+    len: usize,
+    phantom: PhantomData<T>,
+}
+
+/// A buffer that wraps caller-owned host memory without copying it (see
+/// `Device::create_host_buffer()`).
+///
+/// The buffer borrows the memory for its whole lifetime. Dropping it waits until the GPU has
+/// stopped using the memory, which is why it must not be leaked.
+pub struct HostBuffer<'a> {
+    buffer: Buffer,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+/// Types that can be safely viewed as, and created from, raw bytes.
+///
+/// This is what allows values to be copied to and from the GPU without decoding.
+///
+/// # Safety
+///
+/// Values of the type are reinterpreted as bytes when uploaded and built from arbitrary bytes
+/// when read back, so implementors must guarantee that:
+///
+/// * The type contains no padding bytes, since these are uninitialized and must not be read.
+///   Structs must be `#[repr(C)]` with fields laid out so that the compiler inserts no padding.
+///
+/// * Every bit pattern of the right size is a valid value. This rules out `bool`, `char`, enums,
+///   and references.
+///
+/// * The type contains no pointers, since these would be meaningless on the GPU.
+pub unsafe trait Pod: Copy + 'static {}
+
 /// CPU-side data to initialize a buffer store with.
 #[derive(Clone, Copy)]
 pub struct HostAllocatedData<'a>(&'a [u8]);
@@ -64,9 +196,19 @@ impl Drop for Buffer {
 impl Buffer {
     #[doc(hidden)]
     #[inline]
-    pub unsafe fn from_raw_data(data: usize, functions: &'static BufferFunctions) -> Buffer {
+    pub unsafe fn from_raw_data(data: usize,
+                                size: usize,
+                                protection: Protection,
+                                location: MemoryLocation,
+                                functions: &'static BufferFunctions)
+                                -> Buffer {
         Buffer {
             data: data,
+            size: size,
+            protection: protection,
+            location: location,
+            memory: None,
+            label: None,
             functions: functions,
         }
     }
@@ -76,6 +218,314 @@ impl Buffer {
     pub fn data(&self) -> usize {
         self.data
     }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn functions(&self) -> &'static BufferFunctions {
+        self.functions
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn set_tracked_memory(&mut self, memory: TrackedMemory) {
+        self.memory = Some(memory)
+    }
+
+    /// Sets the debug label of this buffer, under which its memory is reported by
+    /// `Device::memory_usage()`.
+    ///
+    /// Every buffer keeps its label, but only the memory of buffers that the device allocated is
+    /// tracked, so the memory of imported and host buffers is not reported.
+    #[inline]
+    pub fn set_label(&mut self, label: Option<&str>) {
+        if let Some(ref mut memory) = self.memory {
+            memory.set_label(label)
+        }
+        self.label = label.map(|label| label.to_owned())
+    }
+
+    /// Returns the debug label of this buffer, if any.
+    #[inline]
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_ref().map(|label| &**label)
+    }
+
+    /// Returns the size of this buffer in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the GPU-side protection that this buffer was created with.
+    #[inline]
+    pub fn protection(&self) -> Protection {
+        self.protection
+    }
+
+    /// Returns the memory location that this buffer was created with.
+    #[inline]
+    pub fn memory_location(&self) -> MemoryLocation {
+        self.location
+    }
+
+    /// Returns `Error::OutOfBounds` unless the given range lies within this buffer.
+    #[inline]
+    pub fn check_range(&self, range: &Range<usize>) -> Result<(), Error> {
+        if range.start <= range.end && range.end <= self.size {
+            Ok(())
+        } else {
+            Err(Error::OutOfBounds)
+        }
+    }
+
+    /// Maps the given range of this buffer into CPU memory for reading, so that it can be read
+    /// without copying.
+    ///
+    /// This waits for commands previously submitted to `queue` to finish. Unlike
+    /// `Queue::submit_read_buffer()`, this avoids an extra copy into a separate allocation.
+    ///
+    /// The range is mapped on every call and unmapped when the returned guard is dropped;
+    /// persistent coherent mappings are not used, even where the driver supports them.
+    #[inline]
+    pub fn map<'a>(&'a self, queue: &'a Queue, range: Range<usize>)
+                   -> Result<MappedBuffer<'a>, Error> {
+        Ok(MappedBuffer {
+            mapping: try!(self.map_with_mode(queue, range, MapMode::Read)),
+        })
+    }
+
+    /// Maps the given range of this buffer into CPU memory for reading and writing.
+    ///
+    /// This is like `map()`, except that the contents are written back when the returned guard is
+    /// dropped.
+    #[inline]
+    pub fn map_mut<'a>(&'a self, queue: &'a Queue, range: Range<usize>)
+                       -> Result<MappedBufferMut<'a>, Error> {
+        Ok(MappedBufferMut {
+            mapping: try!(self.map_with_mode(queue, range, MapMode::ReadWrite)),
+            mode: MapMode::ReadWrite,
+        })
+    }
+
+    /// Maps the given range of this buffer into CPU memory for writing only.
+    ///
+    /// This is like `map_mut()`, except that the mapped bytes start out undefined rather than
+    /// holding the buffer's contents, which spares the driver from copying them to the CPU.
+    #[inline]
+    pub fn map_write<'a>(&'a self, queue: &'a Queue, range: Range<usize>)
+                         -> Result<MappedBufferMut<'a>, Error> {
+        Ok(MappedBufferMut {
+            mapping: try!(self.map_with_mode(queue, range, MapMode::Write)),
+            mode: MapMode::Write,
+        })
+    }
+
+    fn map_with_mode<'a>(&'a self, queue: &'a Queue, range: Range<usize>, mode: MapMode)
+                         -> Result<Mapping<'a>, Error> {
+        try!(self.check_range(&range));
+        match (self.location, mode) {
+            (MemoryLocation::Upload, MapMode::Write) |
+            (MemoryLocation::Readback, MapMode::Read) => {}
+            (MemoryLocation::Upload, _) => {
+                return Err(Error::Unsupported("mapping upload buffers for reading"))
+            }
+            (MemoryLocation::Readback, _) => {
+                return Err(Error::Unsupported("mapping readback buffers for writing"))
+            }
+            // Read-only host buffers may wrap immutable memory, which OpenCL may write to even
+            // when mapping for reading. Since the GPU never writes to them, the wrapped memory is
+            // always up to date, so there is no need to map them anyway.
+            (MemoryLocation::Host, _) if self.protection == Protection::ReadOnly => {
+                return Err(Error::Unsupported("mapping read-only host buffers"))
+            }
+            _ => {}
+        }
+        let len = range.end - range.start;
+        let ptr = try!((self.functions.map)(self, queue, range, mode));
+        Ok(Mapping {
+            buffer: self,
+            queue: queue,
+            ptr: ptr,
+            len: len,
+        })
+    }
+
+    /// Copies the contents of this buffer to the start of `external_buffer`.
+    ///
+    /// The contents are copied on the GPU after the commands previously submitted to `queue`, so
+    /// that, for example, a mesh generated by a compute shader can be drawn with OpenGL as a
+    /// vertex or index buffer without a round trip through the CPU. The copy is a snapshot: later
+    /// commands do not update it. On OpenCL, this blocks until the copy is complete, and requires
+    /// a device opened with `Instance::open_device_with_gl_sharing()`.
+    ///
+    /// To share storage with OpenGL rather than copy, import the OpenGL buffer with
+    /// `Device::import_buffer()` instead.
+    ///
+    /// Fails with `Error::OutOfBounds` if `external_buffer` is smaller than this buffer; it is
+    /// never reallocated.
+    #[inline]
+    pub fn copy_to(&self, queue: &Queue, external_buffer: &ExternalBuffer) -> Result<(), Error> {
+        (self.functions.copy_to)(self, queue, external_buffer)
+    }
+
+    /// Returns a view of `len` bytes of this buffer, starting at `offset`.
+    ///
+    /// `offset` must be a multiple of `Device::buffer_offset_alignment()`, or binding the slice
+    /// will fail with `Error::Misaligned`.
+    ///
+    /// Panics if `len` is zero, since neither backend can bind an empty range, or if the range
+    /// does not lie within the buffer.
+    #[inline]
+    pub fn slice<'a>(&'a self, offset: usize, len: usize) -> BufferSlice<'a> {
+        assert!(len != 0, "Buffer slice is empty!");
+        assert!(offset.checked_add(len).map_or(false, |end| end <= self.size),
+                "Buffer slice out of bounds!");
+        BufferSlice {
+            buffer: self,
+            offset: offset,
+            len: len,
+        }
+    }
+}
+
+impl<'a> Deref for MappedBuffer<'a> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self.mapping.ptr, self.mapping.len)
+        }
+    }
+}
+
+impl<'a> MappedBufferMut<'a> {
+    /// Returns how this buffer was mapped: `MapMode::ReadWrite` if it came from
+    /// `Buffer::map_mut()`, or `MapMode::Write` if it came from `Buffer::map_write()`.
+    #[inline]
+    pub fn mode(&self) -> MapMode {
+        self.mode
+    }
+}
+
+impl<'a> Deref for MappedBufferMut<'a> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(self.mapping.ptr, self.mapping.len)
+        }
+    }
+}
+
+impl<'a> DerefMut for MappedBufferMut<'a> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(self.mapping.ptr, self.mapping.len)
+        }
+    }
+}
+
+impl<'a> Drop for Mapping<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            (self.buffer.functions.unmap)(self.buffer, self.queue, self.ptr)
+        }
+    }
+}
+
+impl<'a> BufferSlice<'a> {
+    /// Returns the buffer that this slice is a view of.
+    #[inline]
+    pub fn buffer(&self) -> &'a Buffer {
+        self.buffer
+    }
+
+    /// Returns the offset of the start of this slice in bytes from the start of the buffer.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the size of this slice in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if this slice is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<'a> HostBuffer<'a> {
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn from_buffer(buffer: Buffer) -> HostBuffer<'a> {
+        HostBuffer {
+            buffer: buffer,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the underlying buffer, for passing to programs.
+    #[inline]
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+}
+
+impl<T> TypedBuffer<T> where T: Pod {
+    #[doc(hidden)]
+    #[inline]
+    pub fn from_buffer(buffer: Buffer, len: usize) -> TypedBuffer<T> {
+        TypedBuffer {
+            buffer: buffer,
+            len: len,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the underlying untyped buffer, for passing to programs.
+    #[inline]
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    /// Returns the number of elements in this buffer.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if this buffer has no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads the contents of this buffer back to main memory.
+    ///
+    /// * `queue` specifies the queue to read on. This waits for commands previously submitted to
+    ///   it to finish.
+    ///
+    /// * `events` is a list of sync events that must complete before the read can begin.
+    pub fn read_to_vec(&self, queue: &Queue, events: &[SyncEvent]) -> Result<Vec<T>, Error> {
+        let size = try!(self.len.checked_mul(mem::size_of::<T>()).ok_or(Error::OutOfBounds));
+        unsafe {
+            // `T: Pod` means that the all-zero bit pattern, like any other, is a valid `T`.
+            let mut elements = vec![mem::zeroed::<T>(); self.len];
+            {
+                let bytes = slice::from_raw_parts_mut(elements.as_mut_ptr() as *mut u8, size);
+                try!(queue.submit_read_buffer(bytes, &self.buffer, 0, events));
+            }
+            Ok(elements)
+        }
+    }
 }
 
 impl<'a> HostAllocatedData<'a> {
@@ -95,7 +545,7 @@ impl<'a> HostAllocatedData<'a> {
 
     /// Constructs a `HostAllocatedData` from the given slice.
     #[inline]
-    pub fn new<'b, T>(slice: &'b [T]) -> HostAllocatedData<'b> {
+    pub fn new<'b, T>(slice: &'b [T]) -> HostAllocatedData<'b> where T: Pod {
         unsafe {
             HostAllocatedData(slice::from_raw_parts(slice.as_ptr() as *const u8,
                                                     slice.len() * mem::size_of::<T>()))
@@ -103,3 +553,22 @@ impl<'a> HostAllocatedData<'a> {
     }
 }
 
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(
+            unsafe impl Pod for $ty {}
+        )*
+    }
+}
+
+impl_pod!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64);
+
+macro_rules! impl_pod_for_arrays {
+    ($($len:expr),*) => {
+        $(
+            unsafe impl<T> Pod for [T; $len] where T: Pod {}
+        )*
+    }
+}
+
+impl_pod_for_arrays!(1, 2, 3, 4, 8, 16, 32, 64);
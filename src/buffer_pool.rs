@@ -0,0 +1,399 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Suballocation of many small buffers out of a few large ones.
+//!
+//! Creating a buffer is a comparatively expensive driver call. A `BufferPool` reserves large
+//! blocks up front and hands out aligned ranges of them, which are bound to programs as buffer
+//! slices (see `Buffer::slice()`).
+
+use buffer::{Buffer, BufferData, BufferSlice, MemoryLocation, Protection};
+use device::Device;
+use error::Error;
+use std::cmp;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A pool of GPU memory that hands out ranges of a few large buffers.
+pub struct BufferPool<'a> {
+    device: &'a Device,
+    protection: Protection,
+    location: MemoryLocation,
+    block_size: usize,
+    alignment: usize,
+    blocks: Vec<Block>,
+    id: usize,
+    // Incremented by `reset()`, so that allocations made before it are recognized as stale.
+    generation: usize,
+}
+
+/// A range of memory allocated from a `BufferPool`.
+///
+/// Return this to the pool it came from with `BufferPool::free()`.
+#[derive(Debug)]
+pub struct Allocation {
+    pool: usize,
+    generation: usize,
+    block: usize,
+    offset: usize,
+    size: usize,
+}
+
+/// Statistics about the memory in a `BufferPool`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PoolStats {
+    /// The number of blocks that the pool has reserved.
+    pub block_count: usize,
+    /// The total size of all reserved blocks in bytes.
+    pub reserved_bytes: usize,
+    /// The number of bytes currently handed out, including alignment padding.
+    pub allocated_bytes: usize,
+    /// The number of reserved bytes that are not handed out.
+    pub free_bytes: usize,
+    /// The number of disjoint free ranges across all blocks.
+    pub free_range_count: usize,
+    /// The size of the largest free range in bytes, which bounds the largest allocation that can
+    /// be made without reserving a new block.
+    pub largest_free_range: usize,
+}
+
+struct Block {
+    buffer: Buffer,
+    free_list: FreeList,
+}
+
+// The free ranges of a block, sorted by start, with adjacent ranges coalesced.
+struct FreeList {
+    size: usize,
+    ranges: Vec<Range<usize>>,
+}
+
+impl<'a> BufferPool<'a> {
+    /// Creates a new, empty pool that reserves blocks of `block_size` bytes from `device` with the
+    /// given protection and memory location.
+    ///
+    /// Allocations are aligned to `Device::buffer_offset_alignment()`, so that they can be bound
+    /// to programs.
+    pub fn new(device: &'a Device,
+               protection: Protection,
+               location: MemoryLocation,
+               block_size: usize)
+               -> Result<BufferPool<'a>, Error> {
+        let alignment = cmp::max(try!(device.buffer_offset_alignment()), 1);
+        Ok(BufferPool {
+            device: device,
+            protection: protection,
+            location: location,
+            block_size: block_size,
+            alignment: alignment,
+            blocks: vec![],
+            id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            generation: 0,
+        })
+    }
+
+    /// Allocates `size` bytes, reserving a new block if no existing one has room.
+    ///
+    /// Allocations larger than the block size get a block of their own. Fails with
+    /// `Error::OutOfMemory` if `size` is too large to be aligned.
+    pub fn allocate(&mut self, size: usize) -> Result<Allocation, Error> {
+        let size = try!(round_up(cmp::max(size, 1), self.alignment).ok_or(Error::OutOfMemory));
+
+        for block_index in 0..self.blocks.len() {
+            if let Some(offset) = self.blocks[block_index].free_list.allocate(size) {
+                return Ok(self.allocation(block_index, offset, size))
+            }
+        }
+
+        let block_size = cmp::max(size, self.block_size);
+        let buffer = try!(self.device.create_buffer_in(self.protection,
+                                                       self.location,
+                                                       BufferData::Uninitialized(block_size)));
+        let mut block = Block {
+            buffer: buffer,
+            free_list: FreeList::new(block_size),
+        };
+        let offset = block.free_list.allocate(size).unwrap();
+        self.blocks.push(block);
+
+        let block_index = self.blocks.len() - 1;
+        Ok(self.allocation(block_index, offset, size))
+    }
+
+    fn allocation(&self, block: usize, offset: usize, size: usize) -> Allocation {
+        Allocation {
+            pool: self.id,
+            generation: self.generation,
+            block: block,
+            offset: offset,
+            size: size,
+        }
+    }
+
+    /// Returns an allocation to the pool.
+    ///
+    /// Allocations made before the last `reset()` were already freed by it, so freeing them again
+    /// does nothing. Panics if the allocation came from a different pool.
+    pub fn free(&mut self, allocation: Allocation) {
+        assert!(allocation.pool == self.id, "Allocation freed into the wrong pool!");
+        if allocation.generation != self.generation {
+            return
+        }
+        self.blocks[allocation.block]
+            .free_list
+            .free(allocation.offset..allocation.offset + allocation.size)
+    }
+
+    /// Frees every allocation at once, keeping the reserved blocks for reuse.
+    ///
+    /// This suits transient allocations that all die at the end of a frame. Any outstanding
+    /// `Allocation` values must not be used afterward; freeing them does nothing.
+    pub fn reset(&mut self) {
+        for block in &mut self.blocks {
+            block.free_list = FreeList::new(block.buffer.size());
+        }
+        self.generation += 1;
+    }
+
+    /// Releases blocks at the end of the pool that have no allocations in them.
+    ///
+    /// Only trailing blocks are released, so that outstanding allocations stay valid.
+    pub fn trim(&mut self) {
+        let len = self.blocks.len() -
+            trailing_empty_count(self.blocks.iter().map(|block| &block.free_list));
+        self.blocks.truncate(len);
+    }
+
+    /// Returns the buffer slice that an allocation refers to, for passing to programs.
+    ///
+    /// Panics if the allocation came from a different pool or was made before the last
+    /// `reset()`.
+    #[inline]
+    pub fn slice<'b>(&'b self, allocation: &Allocation) -> BufferSlice<'b> {
+        assert!(allocation.pool == self.id && allocation.generation == self.generation,
+                "Allocation does not belong to this pool!");
+        self.blocks[allocation.block].buffer.slice(allocation.offset, allocation.size)
+    }
+
+    /// Returns statistics about the memory in this pool.
+    ///
+    /// The ratio of `largest_free_range` to `free_bytes` measures fragmentation: the closer it is
+    /// to 1, the less fragmented the free memory is.
+    pub fn stats(&self) -> PoolStats {
+        stats(self.blocks.iter().map(|block| &block.free_list))
+    }
+}
+
+impl Allocation {
+    /// Returns the offset of this allocation within its block, in bytes.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the size of this allocation in bytes, rounded up to the pool's alignment.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl FreeList {
+    fn new(size: usize) -> FreeList {
+        FreeList {
+            size: size,
+            ranges: vec![0..size],
+        }
+    }
+
+    // First fit. Sizes are multiples of the alignment and blocks start aligned, so every free
+    // range starts aligned too.
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        let index = match self.ranges.iter().position(|range| range.end - range.start >= size) {
+            Some(index) => index,
+            None => return None,
+        };
+
+        let offset = self.ranges[index].start;
+        self.ranges[index].start += size;
+        if self.ranges[index].start == self.ranges[index].end {
+            self.ranges.remove(index);
+        }
+        Some(offset)
+    }
+
+    fn free(&mut self, range: Range<usize>) {
+        let index = self.ranges
+                        .iter()
+                        .position(|free_range| free_range.start > range.start)
+                        .unwrap_or(self.ranges.len());
+
+        // Freeing a range twice would let later allocations alias each other.
+        debug_assert!(range.end <= self.size, "Freed range is out of bounds!");
+        debug_assert!(index == 0 || self.ranges[index - 1].end <= range.start,
+                      "Freed range overlaps a free range!");
+        debug_assert!(index == self.ranges.len() || range.end <= self.ranges[index].start,
+                      "Freed range overlaps a free range!");
+
+        self.ranges.insert(index, range);
+
+        // Coalesce with the following range, then with the preceding one.
+        if index + 1 < self.ranges.len() && self.ranges[index].end == self.ranges[index + 1].start {
+            self.ranges[index].end = self.ranges[index + 1].end;
+            self.ranges.remove(index + 1);
+        }
+        if index > 0 && self.ranges[index - 1].end == self.ranges[index].start {
+            self.ranges[index - 1].end = self.ranges[index].end;
+            self.ranges.remove(index);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.len() == 1 && self.ranges[0] == (0..self.size)
+    }
+}
+
+fn trailing_empty_count<'a, I>(free_lists: I) -> usize
+                              where I: DoubleEndedIterator<Item = &'a FreeList> {
+    free_lists.rev().take_while(|free_list| free_list.is_empty()).count()
+}
+
+fn stats<'a, I>(free_lists: I) -> PoolStats where I: Iterator<Item = &'a FreeList> {
+    let mut stats = PoolStats {
+        block_count: 0,
+        reserved_bytes: 0,
+        allocated_bytes: 0,
+        free_bytes: 0,
+        free_range_count: 0,
+        largest_free_range: 0,
+    };
+    for free_list in free_lists {
+        stats.block_count += 1;
+        stats.reserved_bytes += free_list.size;
+        for range in &free_list.ranges {
+            stats.free_bytes += range.end - range.start;
+            stats.largest_free_range = cmp::max(stats.largest_free_range, range.end - range.start);
+        }
+        stats.free_range_count += free_list.ranges.len();
+    }
+    stats.allocated_bytes = stats.reserved_bytes - stats.free_bytes;
+    stats
+}
+
+// Returns `None` on overflow.
+fn round_up(value: usize, alignment: usize) -> Option<usize> {
+    value.checked_add(alignment - 1).map(|value| value / alignment * alignment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FreeList, PoolStats, round_up, stats, trailing_empty_count};
+
+    #[test]
+    fn allocate_splits_the_first_fitting_range() {
+        let mut free_list = FreeList::new(256);
+        assert_eq!(free_list.allocate(64), Some(0));
+        assert_eq!(free_list.allocate(64), Some(64));
+        assert_eq!(free_list.ranges, vec![128..256]);
+        assert_eq!(free_list.allocate(128), Some(128));
+        assert!(free_list.ranges.is_empty());
+        assert_eq!(free_list.allocate(1), None);
+    }
+
+    #[test]
+    fn allocate_skips_ranges_that_are_too_small() {
+        let mut free_list = FreeList::new(256);
+        free_list.allocate(256);
+        free_list.free(0..32);
+        free_list.free(64..192);
+        assert_eq!(free_list.allocate(64), Some(64));
+        assert_eq!(free_list.ranges, vec![0..32, 128..192]);
+    }
+
+    #[test]
+    fn free_coalesces_with_the_next_range() {
+        let mut free_list = FreeList::new(256);
+        free_list.allocate(128);
+        free_list.free(64..128);
+        assert_eq!(free_list.ranges, vec![64..256]);
+    }
+
+    #[test]
+    fn free_coalesces_with_the_previous_range() {
+        let mut free_list = FreeList::new(256);
+        free_list.allocate(256);
+        free_list.free(0..64);
+        free_list.free(64..128);
+        assert_eq!(free_list.ranges, vec![0..128]);
+    }
+
+    #[test]
+    fn free_coalesces_with_both_neighbors() {
+        let mut free_list = FreeList::new(192);
+        free_list.allocate(192);
+        free_list.free(0..64);
+        free_list.free(128..192);
+        assert_eq!(free_list.ranges, vec![0..64, 128..192]);
+        free_list.free(64..128);
+        assert_eq!(free_list.ranges, vec![0..192]);
+        assert!(free_list.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn double_free_is_caught() {
+        let mut free_list = FreeList::new(256);
+        free_list.allocate(64);
+        free_list.free(0..64);
+        free_list.free(0..64);
+    }
+
+    #[test]
+    fn trim_releases_only_trailing_empty_blocks() {
+        let empty = FreeList::new(256);
+        let mut used = FreeList::new(256);
+        used.allocate(64);
+        assert_eq!(trailing_empty_count(vec![&empty, &used, &empty, &empty].into_iter()), 2);
+        assert_eq!(trailing_empty_count(vec![&empty, &used].into_iter()), 0);
+        assert_eq!(trailing_empty_count(vec![&empty, &empty].into_iter()), 2);
+
+        // Freeing the last allocation makes a block empty again.
+        used.free(0..64);
+        assert_eq!(trailing_empty_count(vec![&used].into_iter()), 1);
+    }
+
+    #[test]
+    fn stats_sum_over_blocks() {
+        let mut first = FreeList::new(256);
+        first.allocate(64);
+        first.allocate(64);
+        first.free(0..64);
+        let second = FreeList::new(512);
+
+        assert_eq!(stats(vec![&first, &second].into_iter()), PoolStats {
+            block_count: 2,
+            reserved_bytes: 768,
+            allocated_bytes: 64,
+            free_bytes: 704,
+            free_range_count: 3,
+            largest_free_range: 512,
+        });
+    }
+
+    #[test]
+    fn round_up_reports_overflow() {
+        assert_eq!(round_up(1, 256), Some(256));
+        assert_eq!(round_up(256, 256), Some(256));
+        assert_eq!(round_up(usize::max_value(), 256), None);
+    }
+}
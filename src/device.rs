@@ -10,31 +10,70 @@
 
 //! A GPU that supports compute.
 
-use buffer::{Buffer, BufferData, Protection};
+use buffer::{Buffer, BufferData, ExternalBuffer, HostAllocatedData, HostBuffer, MemoryLocation};
+use buffer::{Pod, Protection, TypedBuffer};
 use error::Error;
 use euclid::Size2D;
-use image::{Format, Image};
+use image::{ExternalImage, Format, Image};
+use memory::{DriverMemoryInfo, MemoryTracker, MemoryUsage};
+use profile_event::ClockCalibration;
 use program::Program;
-use queue::Queue;
+use queue::{Queue, QueueOptions};
+use sampler::{AddressMode, Filter, Sampler};
+use std::mem;
+use std::sync::Arc;
+use sync_event::SyncEvent;
 
 /// A GPU that supports compute.
 pub struct Device {
     data: usize,
+    memory: Arc<MemoryTracker>,
     functions: &'static DeviceFunctions,
 }
 
 #[doc(hidden)]
 pub struct DeviceFunctions {
     pub destroy: unsafe extern "Rust" fn(this: &Device),
-    pub create_queue: extern "Rust" fn(this: &Device) -> Result<Queue, Error>,
+    pub create_queue: extern "Rust" fn(this: &Device, options: &QueueOptions)
+                                       -> Result<Queue, Error>,
     pub create_program: extern "Rust" fn(this: &Device, source: &str) -> Result<Program, Error>,
-    pub create_buffer: extern "Rust" fn(this: &Device, protection: Protection, data: BufferData)
+    pub create_buffer: extern "Rust" fn(this: &Device,
+                                        protection: Protection,
+                                        location: MemoryLocation,
+                                        data: BufferData)
                                         -> Result<Buffer, Error>,
+    pub create_host_buffer: unsafe extern "Rust" fn(this: &Device,
+                                                    protection: Protection,
+                                                    data: *mut u8,
+                                                    size: usize)
+                                                    -> Result<Buffer, Error>,
     pub create_image: extern "Rust" fn(this: &Device,
                                        format: Format,
                                        protection: Protection,
                                        size: &Size2D<u32>)
                                        -> Result<Image, Error>,
+    pub create_user_event: extern "Rust" fn(this: &Device) -> Result<SyncEvent, Error>,
+    pub calibrate_clock: extern "Rust" fn(this: &Device) -> Result<ClockCalibration, Error>,
+    pub buffer_offset_alignment: extern "Rust" fn(this: &Device) -> Result<usize, Error>,
+    pub create_sampler: extern "Rust" fn(this: &Device,
+                                         filter: Filter,
+                                         address_mode: AddressMode,
+                                         normalized_coords: bool)
+                                         -> Result<Sampler, Error>,
+    pub driver_memory_info: extern "Rust" fn(this: &Device)
+                                             -> Result<Option<DriverMemoryInfo>, Error>,
+    pub import_buffer: extern "Rust" fn(this: &Device,
+                                        external_buffer: &ExternalBuffer,
+                                        protection: Protection)
+                                        -> Result<Buffer, Error>,
+    pub import_image: extern "Rust" fn(this: &Device,
+                                       external_image: &ExternalImage,
+                                       protection: Protection)
+                                       -> Result<Image, Error>,
+    pub supports_image_format: extern "Rust" fn(this: &Device,
+                                                format: Format,
+                                                protection: Protection)
+                                                -> Result<bool, Error>,
 }
 
 impl Drop for Device {
@@ -51,6 +90,7 @@ impl Device {
     pub unsafe fn from_raw_data(data: usize, functions: &'static DeviceFunctions) -> Device {
         Device {
             data: data,
+            memory: MemoryTracker::new(),
             functions: functions,
         }
     }
@@ -61,10 +101,20 @@ impl Device {
         self.data
     }
 
-    /// Creates a new command queue on which jobs can be submitted.
+    /// Creates a new in-order command queue on which jobs can be submitted, with profiling
+    /// disabled.
     #[inline]
     pub fn create_queue(&self) -> Result<Queue, Error> {
-        (self.functions.create_queue)(self)
+        self.create_queue_with_options(&QueueOptions::default())
+    }
+
+    /// Creates a new command queue with the given options.
+    ///
+    /// A device may have any number of queues. Commands on different queues are unordered with
+    /// respect to one another unless sync events are used to order them.
+    #[inline]
+    pub fn create_queue_with_options(&self, options: &QueueOptions) -> Result<Queue, Error> {
+        (self.functions.create_queue)(self, options)
     }
 
     /// Creates, compiles, and links a new compute program to execute on the GPU with the given
@@ -81,16 +131,243 @@ impl Device {
     #[inline]
     pub fn create_buffer(&self, protection: Protection, data: BufferData)
                          -> Result<Buffer, Error> {
-        (self.functions.create_buffer)(self, protection, data)
+        self.create_buffer_in(protection, MemoryLocation::DeviceLocal, data)
+    }
+
+    /// Creates a new block of GPU memory with the given GPU-side protection, initialized with the
+    /// supplied data, in the memory location best suited to how it will be used.
+    ///
+    /// Fails with `Error::OutOfMemory` if the buffer would exceed the device's memory budget, or
+    /// with `Error::Unsupported` if the buffer would be empty, since neither backend can create
+    /// one.
+    #[inline]
+    pub fn create_buffer_in(&self,
+                            protection: Protection,
+                            location: MemoryLocation,
+                            data: BufferData)
+                            -> Result<Buffer, Error> {
+        let size = match data {
+            BufferData::HostAllocated(ref host_data) => host_data.size(),
+            BufferData::Uninitialized(size) => size,
+        };
+        if size == 0 {
+            return Err(Error::Unsupported("empty buffers"))
+        }
+        let memory = try!(MemoryTracker::reserve(&self.memory, size));
+        let mut buffer = try!((self.functions.create_buffer)(self, protection, location, data));
+        buffer.set_tracked_memory(memory);
+        Ok(buffer)
+    }
+
+    /// Creates a read-only buffer that uses `data` as its storage, without copying it.
+    ///
+    /// This is meant for large inputs such as memory-mapped files. `data` should be page-aligned;
+    /// some backends fail with `Error::Misaligned` otherwise. Returns `Error::Unsupported` if the
+    /// backend cannot wrap host memory, in which case `create_buffer()` with
+    /// `BufferData::HostAllocated` is the fallback. Read-only host buffers cannot be mapped;
+    /// read `data` directly instead.
+    ///
+    /// On OpenGL this requires `GL_AMD_pinned_memory`. Like `create_buffer_in()`, this fails with
+    /// `Error::Unsupported` if `data` is empty.
+    ///
+    /// # Safety
+    ///
+    /// The returned buffer must be dropped before `data` is freed or reused. Dropping it is what
+    /// waits for the GPU to stop using the memory, and the borrow alone cannot ensure that it is
+    /// dropped: leaking it, for example with `mem::forget()`, ends the borrow while the GPU may
+    /// still access `data`.
+    #[inline]
+    pub unsafe fn create_host_buffer<'a>(&self, data: &'a [u8]) -> Result<HostBuffer<'a>, Error> {
+        if data.is_empty() {
+            return Err(Error::Unsupported("empty buffers"))
+        }
+        // The GPU never writes to a read-only buffer, so the memory is not mutated.
+        let buffer = try!((self.functions.create_host_buffer)(self,
+                                                              Protection::ReadOnly,
+                                                              data.as_ptr() as *mut u8,
+                                                              data.len()));
+        Ok(HostBuffer::from_buffer(buffer))
+    }
+
+    /// Creates a buffer that uses `data` as its storage, without copying it, and that the GPU may
+    /// write to according to `protection`.
+    ///
+    /// See `create_host_buffer()` for restrictions. Like buffers from `create_host_buffer()`,
+    /// buffers created with `Protection::ReadOnly` cannot be mapped.
+    ///
+    /// # Safety
+    ///
+    /// As with `create_host_buffer()`, the returned buffer must be dropped before `data` is freed
+    /// or used again.
+    #[inline]
+    pub unsafe fn create_host_buffer_mut<'a>(&self, protection: Protection, data: &'a mut [u8])
+                                             -> Result<HostBuffer<'a>, Error> {
+        if data.is_empty() {
+            return Err(Error::Unsupported("empty buffers"))
+        }
+        let buffer = try!((self.functions.create_host_buffer)(self,
+                                                              protection,
+                                                              data.as_mut_ptr(),
+                                                              data.len()));
+        Ok(HostBuffer::from_buffer(buffer))
+    }
+
+    /// Creates a new buffer initialized with the contents of `elements`.
+    #[inline]
+    pub fn create_buffer_from_slice<T>(&self, protection: Protection, elements: &[T])
+                                       -> Result<TypedBuffer<T>, Error>
+                                       where T: Pod {
+        let data = BufferData::HostAllocated(HostAllocatedData::new(elements));
+        let buffer = try!(self.create_buffer(protection, data));
+        Ok(TypedBuffer::from_buffer(buffer, elements.len()))
+    }
+
+    /// Creates a new buffer with room for `len` elements of type `T`.
+    ///
+    /// The initial contents of the buffer are undefined. Fails with `Error::OutOfMemory` if the
+    /// size in bytes does not fit in a `usize`.
+    #[inline]
+    pub fn create_typed_buffer<T>(&self, protection: Protection, len: usize)
+                                  -> Result<TypedBuffer<T>, Error>
+                                  where T: Pod {
+        let size = try!(len.checked_mul(mem::size_of::<T>()).ok_or(Error::OutOfMemory));
+        let data = BufferData::Uninitialized(size);
+        let buffer = try!(self.create_buffer(protection, data));
+        Ok(TypedBuffer::from_buffer(buffer, len))
     }
 
     /// Creates a new image of the given format, GPU-side protection, and size.
     ///
-    /// The initial contents of the image are undefined.
+    /// The initial contents of the image are undefined. Fails with `Error::Unsupported` if the
+    /// device does not support `format` with `protection`, or with `Error::OutOfMemory` if the
+    /// image would exceed the device's memory budget or its size in bytes does not fit in a
+    /// `usize`.
     #[inline]
     pub fn create_image(&self, format: Format, protection: Protection, size: &Size2D<u32>)
                         -> Result<Image, Error> {
-        (self.functions.create_image)(self, format, protection, size)
+        if !try!(self.supports_image_format(format, protection)) {
+            return Err(Error::Unsupported("image format"))
+        }
+        let pixel_count = try!((size.width as usize).checked_mul(size.height as usize)
+                                                    .ok_or(Error::OutOfMemory));
+        let byte_size = try!(pixel_count.checked_mul(format.bytes_per_pixel())
+                                        .ok_or(Error::OutOfMemory));
+        let memory = try!(MemoryTracker::reserve(&self.memory, byte_size));
+        let mut image = try!((self.functions.create_image)(self, format, protection, size));
+        image.set_tracked_memory(memory);
+        Ok(image)
+    }
+
+    /// Returns true if images of the given format and GPU-side protection can be created on this
+    /// device.
+    ///
+    /// Programs can't write to sRGB images, so those are supported only when read-only.
+    #[inline]
+    pub fn supports_image_format(&self, format: Format, protection: Protection)
+                                 -> Result<bool, Error> {
+        if format == Format::SRGBA8 &&
+                protection != Protection::ReadOnly && protection != Protection::Constant {
+            return Ok(false)
+        }
+        (self.functions.supports_image_format)(self, format, protection)
+    }
+
+    /// Wraps an existing external buffer, such as an OpenGL buffer object, so that programs can
+    /// use it.
+    ///
+    /// The contents are not copied: programs access the external buffer directly. The external
+    /// buffer is not deleted when the returned buffer is dropped, and its memory is not counted by
+    /// `memory_usage()`. Submit a barrier with `Queue::submit_barrier()` before OpenGL reads
+    /// what programs wrote.
+    ///
+    /// On OpenCL, importing OpenGL objects requires a device opened with
+    /// `Instance::open_device_with_gl_sharing()`.
+    #[inline]
+    pub fn import_buffer(&self, external_buffer: &ExternalBuffer, protection: Protection)
+                         -> Result<Buffer, Error> {
+        (self.functions.import_buffer)(self, external_buffer, protection)
+    }
+
+    /// Wraps an existing external image, such as an OpenGL texture, so that programs can use it.
+    ///
+    /// OpenGL textures must be rectangle textures with one of the formats in `Format`. As with
+    /// `import_buffer()`, the contents are not copied, the texture is not deleted when the
+    /// returned image is dropped, and its memory is not counted by `memory_usage()`.
+    ///
+    /// Without direct state access (OpenGL 4.5 or `GL_ARB_direct_state_access`), checking the
+    /// target of an OpenGL texture relies on the OpenGL error state, so this fails with
+    /// `Error::Failed` if the context has errors that haven't been checked yet.
+    #[inline]
+    pub fn import_image(&self, external_image: &ExternalImage, protection: Protection)
+                        -> Result<Image, Error> {
+        (self.functions.import_image)(self, external_image, protection)
+    }
+
+    /// Creates a new sampler, which controls how images bound with `Uniform::SampledImage` are
+    /// read.
+    ///
+    /// * `filter` specifies how texels are combined when sampling between texel centers.
+    ///
+    /// * `address_mode` specifies what reads outside the image return.
+    ///
+    /// * `normalized_coords` specifies whether programs address the image with coordinates from
+    ///   0 to 1 rather than in texels. OpenGL images can only be sampled with `sampler2DRect`,
+    ///   which uses texel coordinates, so this must be false there; otherwise
+    ///   `Error::Unsupported` is returned.
+    #[inline]
+    pub fn create_sampler(&self,
+                          filter: Filter,
+                          address_mode: AddressMode,
+                          normalized_coords: bool)
+                          -> Result<Sampler, Error> {
+        (self.functions.create_sampler)(self, filter, address_mode, normalized_coords)
+    }
+
+    /// Returns the amount of memory allocated by the buffers and images created on this device,
+    /// along with the driver's own statistics where it reports them.
+    pub fn memory_usage(&self) -> Result<MemoryUsage, Error> {
+        let driver = try!((self.functions.driver_memory_info)(self));
+        Ok(self.memory.usage(driver))
+    }
+
+    /// Limits the total size of the buffers and images on this device to `budget` bytes, or
+    /// removes the limit if `budget` is `None`.
+    ///
+    /// Once the budget is reached, creating buffers and images fails with `Error::OutOfMemory`.
+    /// Lowering the budget below current usage does not free anything.
+    #[inline]
+    pub fn set_memory_budget(&self, budget: Option<usize>) {
+        self.memory.set_budget(budget)
+    }
+
+    /// Creates a new sync event that does not occur until the CPU signals it with
+    /// `SyncEvent::signal()`.
+    ///
+    /// Commands that wait on the event can be submitted before it is signaled, which makes it
+    /// possible to queue up GPU work that depends on data that the CPU is still producing.
+    ///
+    /// The OpenGL backend has no way to defer submitted commands, so there submitting a command
+    /// that waits on an unsignaled user event fails with `Error::Unsupported`. (Blocking instead
+    /// would deadlock whenever the submitting thread is the one that signals the event.)
+    #[inline]
+    pub fn create_user_event(&self) -> Result<SyncEvent, Error> {
+        (self.functions.create_user_event)(self)
+    }
+
+    /// Samples the GPU clock along with the host clock, so that GPU timestamps from
+    /// `ProfileEvent::timestamps()` can be converted to host time.
+    ///
+    /// This may block until the GPU has caught up with previously submitted work.
+    #[inline]
+    pub fn calibrate_clock(&self) -> Result<ClockCalibration, Error> {
+        (self.functions.calibrate_clock)(self)
+    }
+
+    /// Returns the alignment in bytes that the offsets of buffer slices passed to programs must
+    /// have (see `Buffer::slice()`).
+    #[inline]
+    pub fn buffer_offset_alignment(&self) -> Result<usize, Error> {
+        (self.functions.buffer_offset_alignment)(self)
     }
 }
 
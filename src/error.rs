@@ -23,5 +23,23 @@ pub enum Error {
     ///
     /// The string represents the error message that the driver reported.
     LinkFailed(String),
+    /// A wait timed out before the awaited event occurred.
+    Timeout,
+    /// The backend does not support the requested operation.
+    ///
+    /// The string describes what was unsupported.
+    Unsupported(&'static str),
+    /// The program has no argument with the given name.
+    UniformNotFound(String),
+    /// The program argument with the given name cannot be set from the kind of uniform passed for
+    /// it; for example, a buffer was passed for a scalar argument.
+    UniformMismatch(String),
+    /// An offset did not meet the alignment that the device requires for it.
+    Misaligned,
+    /// A range extended past the end of a buffer.
+    OutOfBounds,
+    /// An allocation would have exceeded the device's memory budget (see
+    /// `Device::set_memory_budget()`).
+    OutOfMemory,
 }
 
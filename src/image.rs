@@ -13,10 +13,13 @@
 use error::Error;
 use gl::types::GLuint;
 use gl;
+use memory::TrackedMemory;
 
 /// An image, which is essentially a read-write texture.
 pub struct Image {
     data: [usize; 2],
+    memory: Option<TrackedMemory>,
+    label: Option<String>,
     functions: &'static ImageFunctions,
 }
 
@@ -30,7 +33,7 @@ pub struct ImageFunctions {
     pub format: extern "Rust" fn(this: &Image) -> Result<Format, Error>,
 }
 
-/// An external resource that can be made to refer to this image.
+/// An external resource that can be made to refer to an image, or that can be imported as one.
 pub enum ExternalImage {
     /// The name of an OpenGL texture.
     GlTexture(u32),
@@ -38,21 +41,46 @@ pub enum ExternalImage {
 
 /// The image format of a texture.
 ///
-/// TODO(pcwalton): Support more.
+/// Not every device supports every format; see `Device::supports_image_format()`.
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Format {
     /// 8-bit unsigned normalized single-channel.
     R8,
+    /// 8-bit unsigned normalized 2-channel.
+    RG8,
     /// 8-bit unsigned normalized 4-channel.
     RGBA8,
+    /// 8-bit unsigned normalized 4-channel, with the color channels in the sRGB color space.
+    ///
+    /// Programs can only read images of this format, through `Uniform::SampledImage`, which
+    /// converts the colors to linear space.
+    SRGBA8,
+    /// 8-bit unsigned integer 4-channel.
+    RGBA8UI,
+    /// 16-bit unsigned normalized single-channel.
+    R16,
+    /// 16-bit 2-channel floating-point.
+    RG16F,
+    /// 16-bit 4-channel floating-point.
+    RGBA16F,
     /// 32-bit single-channel floating-point.
     R32F,
+    /// 32-bit 4-channel floating-point.
+    RGBA32F,
+    /// 32-bit unsigned integer single-channel.
+    R32UI,
+    /// 32-bit signed integer single-channel.
+    R32I,
 }
 
 /// A color.
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Color {
     /// A 4-channel color.
+    ///
+    /// Integer images take the components as they are, and floating-point images take them
+    /// converted to floating point. Other images take them as unsigned normalized bytes, so that
+    /// 255 is 1.0.
     UInt(u32, u32, u32, u32),
 }
 
@@ -70,6 +98,8 @@ impl Image {
     pub unsafe fn from_raw_data(data: [usize; 2], functions: &'static ImageFunctions) -> Image {
         Image {
             data: data,
+            memory: None,
+            label: None,
             functions: functions,
         }
     }
@@ -80,6 +110,37 @@ impl Image {
         self.data
     }
 
+    #[doc(hidden)]
+    #[inline]
+    pub fn functions(&self) -> &'static ImageFunctions {
+        self.functions
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn set_tracked_memory(&mut self, memory: TrackedMemory) {
+        self.memory = Some(memory)
+    }
+
+    /// Sets the debug label of this image, under which its memory is reported by
+    /// `Device::memory_usage()`.
+    ///
+    /// Every image keeps its label, but only the memory of images that the device allocated is
+    /// tracked, so the memory of imported images is not reported.
+    #[inline]
+    pub fn set_label(&mut self, label: Option<&str>) {
+        if let Some(ref mut memory) = self.memory {
+            memory.set_label(label)
+        }
+        self.label = label.map(|label| label.to_owned())
+    }
+
+    /// Returns the debug label of this image, if any.
+    #[inline]
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_ref().map(|label| &**label)
+    }
+
     /// Makes `external_image` reflect the contents of this image.
     ///
     /// This is useful in order to render an image created using a compute shader with OpenGL, for
@@ -109,14 +170,46 @@ impl Image {
 }
 
 impl Format {
+    /// Returns the size of one pixel of this format in bytes.
+    #[inline]
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            Format::R8 => 1,
+            Format::RG8 | Format::R16 => 2,
+            Format::RGBA8 | Format::SRGBA8 | Format::RGBA8UI | Format::RG16F | Format::R32F |
+            Format::R32UI | Format::R32I => 4,
+            Format::RGBA16F => 8,
+            Format::RGBA32F => 16,
+        }
+    }
+
+    /// Returns true if programs read and write this format as integers rather than as
+    /// normalized or floating-point values.
+    #[inline]
+    pub fn is_integer(self) -> bool {
+        match self {
+            Format::RGBA8UI | Format::R32UI | Format::R32I => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if this format stores floating-point values.
+    #[inline]
+    pub fn is_floating_point(self) -> bool {
+        match self {
+            Format::RG16F | Format::RGBA16F | Format::R32F | Format::RGBA32F => true,
+            _ => false,
+        }
+    }
+
     /// Returns the value that should be passed as the `format` parameter to `glTexImage2D()` to
     /// create a texture matching this image format.
     #[inline]
     #[cfg(target_os = "macos")]
     pub fn gl_format(self) -> GLuint {
         match self {
-            Format::R8 | Format::R32F => gl::RED,
-            Format::RGBA8 => gl::BGRA,
+            Format::RGBA8 | Format::SRGBA8 => gl::BGRA,
+            _ => self.gl_common_format(),
         }
     }
 
@@ -125,9 +218,17 @@ impl Format {
     #[inline]
     #[cfg(not(target_os = "macos"))]
     pub fn gl_format(self) -> GLuint {
+        self.gl_common_format()
+    }
+
+    #[inline]
+    fn gl_common_format(self) -> GLuint {
         match self {
-            Format::R8 | Format::R32F => gl::RED,
-            Format::RGBA8 => gl::RGBA,
+            Format::R8 | Format::R16 | Format::R32F => gl::RED,
+            Format::RG8 | Format::RG16F => gl::RG,
+            Format::RGBA8 | Format::SRGBA8 | Format::RGBA16F | Format::RGBA32F => gl::RGBA,
+            Format::R32UI | Format::R32I => gl::RED_INTEGER,
+            Format::RGBA8UI => gl::RGBA_INTEGER,
         }
     }
 
@@ -137,9 +238,8 @@ impl Format {
     #[cfg(target_os = "macos")]
     pub fn gl_type(self) -> GLuint {
         match self {
-            Format::R8 => gl::UNSIGNED_BYTE,
-            Format::RGBA8 => gl::UNSIGNED_INT_8_8_8_8_REV,
-            Format::R32F => gl::FLOAT,
+            Format::RGBA8 | Format::SRGBA8 => gl::UNSIGNED_INT_8_8_8_8_REV,
+            _ => self.gl_common_type(),
         }
     }
 
@@ -148,9 +248,20 @@ impl Format {
     #[inline]
     #[cfg(not(target_os = "macos"))]
     pub fn gl_type(self) -> GLuint {
+        self.gl_common_type()
+    }
+
+    #[inline]
+    fn gl_common_type(self) -> GLuint {
         match self {
-            Format::R8 | Format::RGBA8 => gl::UNSIGNED_BYTE,
-            Format::R32F => gl::FLOAT,
+            Format::R8 | Format::RG8 | Format::RGBA8 | Format::SRGBA8 | Format::RGBA8UI => {
+                gl::UNSIGNED_BYTE
+            }
+            Format::R16 => gl::UNSIGNED_SHORT,
+            Format::RG16F | Format::RGBA16F => gl::HALF_FLOAT,
+            Format::R32F | Format::RGBA32F => gl::FLOAT,
+            Format::R32UI => gl::UNSIGNED_INT,
+            Format::R32I => gl::INT,
         }
     }
 
@@ -159,9 +270,37 @@ impl Format {
     pub fn gl_internal_format(self) -> GLuint {
         match self {
             Format::R8 => gl::R8,
+            Format::RG8 => gl::RG8,
             Format::RGBA8 => gl::RGBA8,
+            Format::SRGBA8 => gl::SRGB8_ALPHA8,
+            Format::RGBA8UI => gl::RGBA8UI,
+            Format::R16 => gl::R16,
+            Format::RG16F => gl::RG16F,
+            Format::RGBA16F => gl::RGBA16F,
             Format::R32F => gl::R32F,
+            Format::RGBA32F => gl::RGBA32F,
+            Format::R32UI => gl::R32UI,
+            Format::R32I => gl::R32I,
         }
     }
-}
 
+    #[doc(hidden)]
+    #[inline]
+    pub fn from_gl_internal_format(internal_format: GLuint) -> Option<Format> {
+        match internal_format {
+            gl::R8 => Some(Format::R8),
+            gl::RG8 => Some(Format::RG8),
+            gl::RGBA8 => Some(Format::RGBA8),
+            gl::SRGB8_ALPHA8 => Some(Format::SRGBA8),
+            gl::RGBA8UI => Some(Format::RGBA8UI),
+            gl::R16 => Some(Format::R16),
+            gl::RG16F => Some(Format::RG16F),
+            gl::RGBA16F => Some(Format::RGBA16F),
+            gl::R32F => Some(Format::R32F),
+            gl::RGBA32F => Some(Format::RGBA32F),
+            gl::R32UI => Some(Format::R32UI),
+            gl::R32I => Some(Format::R32I),
+            _ => None,
+        }
+    }
+}
@@ -27,6 +27,7 @@ pub struct InstanceFunctions {
     pub destroy: unsafe extern "Rust" fn(this: &Instance),
     pub shading_language: extern "Rust" fn(this: &Instance) -> ShadingLanguage,
     pub open_device: extern "Rust" fn(this: &Instance) -> Result<Device, Error>,
+    pub open_device_with_gl_sharing: extern "Rust" fn(this: &Instance) -> Result<Device, Error>,
 }
 
 /// The shading language supported by this instance.
@@ -74,6 +75,18 @@ impl Instance {
         (self.functions.open_device)(self)
     }
 
+    /// Opens a handle to the GPU that can exchange buffers and images with the current OpenGL
+    /// context, through `Device::import_buffer()`, `Device::import_image()`, and
+    /// `Buffer::copy_to()`.
+    ///
+    /// On OpenCL, the device joins the share group of the current OpenGL context, which must
+    /// exist; otherwise `Error::Failed` is returned. On OpenGL, this is the same as
+    /// `open_device()`.
+    #[inline]
+    pub fn open_device_with_gl_sharing(&self) -> Result<Device, Error> {
+        (self.functions.open_device_with_gl_sharing)(self)
+    }
+
     /// Initializes the library and returns a new instance.
     #[cfg(target_os = "macos")]
     pub fn new() -> Result<Instance, Error> {
@@ -0,0 +1,139 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Typed structs of program arguments.
+//!
+//! Rather than building a list of uniforms by hand, declare a struct with one field per program
+//! argument and derive `KernelArgs` for it with the `compute-shader-derive` crate:
+//!
+//! ```ignore
+//! #[derive(KernelArgs)]
+//! struct CaveArgs<'a> {
+//!     image: &'a Image,
+//!     buffer: &'a Buffer,
+//!     seed: u32,
+//!     iterations: u32,
+//! }
+//! ```
+//!
+//! Each field is passed as the program argument with the same name, unless overridden with
+//! `#[uniform(name = "...")]`. Submit the struct with `Queue::submit_compute_with_args()`.
+
+use buffer::{Buffer, BufferSlice, Pod, TypedBuffer};
+use error::Error;
+use image::Image;
+use program::Program;
+use queue::Uniform;
+use sampler::Sampler;
+
+/// A struct whose fields are the arguments to a program.
+///
+/// This is normally derived rather than implemented by hand.
+pub trait KernelArgs {
+    /// Returns each argument as a uniform, paired with the name of the program argument it sets.
+    fn uniforms<'a>(&'a self) -> Vec<(&'static str, Uniform<'a>)>;
+
+    /// Checks that `program` has an argument matching the name and kind of each field of this
+    /// struct.
+    ///
+    /// `Queue::submit_compute_with_args()` performs this check before submitting anything. Call
+    /// it directly after creating the program to catch a mismatched layout up front.
+    fn check(&self, program: &Program) -> Result<(), Error> {
+        program.uniform_indices(&self.uniforms()).map(|_| ())
+    }
+}
+
+/// A buffer field that is passed as `Uniform::ConstantBuffer` rather than `Uniform::Buffer`.
+#[derive(Clone, Copy)]
+pub struct Constant<'a>(pub &'a Buffer);
+
+/// A value that can be passed to a program as a uniform.
+///
+/// Fields of structs deriving `KernelArgs` must implement this.
+pub trait AsUniform {
+    /// Returns this value as a uniform.
+    fn as_uniform<'a>(&'a self) -> Uniform<'a>;
+}
+
+impl<'a> AsUniform for &'a Buffer {
+    #[inline]
+    fn as_uniform<'b>(&'b self) -> Uniform<'b> {
+        Uniform::Buffer(*self)
+    }
+}
+
+impl<'a, T> AsUniform for &'a TypedBuffer<T> where T: Pod {
+    #[inline]
+    fn as_uniform<'b>(&'b self) -> Uniform<'b> {
+        Uniform::Buffer(self.buffer())
+    }
+}
+
+impl<'a> AsUniform for BufferSlice<'a> {
+    #[inline]
+    fn as_uniform<'b>(&'b self) -> Uniform<'b> {
+        Uniform::BufferSlice(*self)
+    }
+}
+
+impl<'a> AsUniform for Constant<'a> {
+    #[inline]
+    fn as_uniform<'b>(&'b self) -> Uniform<'b> {
+        Uniform::ConstantBuffer(self.0)
+    }
+}
+
+impl<'a> AsUniform for &'a Image {
+    #[inline]
+    fn as_uniform<'b>(&'b self) -> Uniform<'b> {
+        Uniform::Image(*self)
+    }
+}
+
+impl<'a> AsUniform for (&'a Image, &'a Sampler) {
+    #[inline]
+    fn as_uniform<'b>(&'b self) -> Uniform<'b> {
+        Uniform::SampledImage(self.0, self.1)
+    }
+}
+
+impl<'a> AsUniform for &'a [u8] {
+    #[inline]
+    fn as_uniform<'b>(&'b self) -> Uniform<'b> {
+        Uniform::Bytes(*self)
+    }
+}
+
+macro_rules! impl_as_uniform {
+    ($($ty:ty => $variant:ident,)*) => {
+        $(
+            impl AsUniform for $ty {
+                #[inline]
+                fn as_uniform<'a>(&'a self) -> Uniform<'a> {
+                    Uniform::$variant(*self)
+                }
+            }
+        )*
+    }
+}
+
+impl_as_uniform! {
+    u32 => U32,
+    [u32; 4] => UVec4,
+    i32 => I32,
+    [i32; 2] => IVec2,
+    [i32; 3] => IVec3,
+    [i32; 4] => IVec4,
+    f32 => F32,
+    [f32; 2] => Vec2,
+    [f32; 3] => Vec3,
+    [f32; 4] => Vec4,
+    [[f32; 4]; 4] => Mat4,
+}
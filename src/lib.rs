@@ -27,12 +27,17 @@ mod api {
 }
 
 pub mod buffer;
+pub mod buffer_pool;
 pub mod device;
 pub mod error;
 pub mod image;
 pub mod instance;
+pub mod kernel_args;
+pub mod memory;
 pub mod profile_event;
+pub mod profiler;
 pub mod program;
 pub mod queue;
+pub mod sampler;
 pub mod sync_event;
 
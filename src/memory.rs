@@ -0,0 +1,145 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Accounting of the GPU memory allocated on a device.
+
+use error::Error;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A snapshot of the memory allocated on a device (see `Device::memory_usage()`).
+#[derive(Clone, PartialEq, Debug)]
+pub struct MemoryUsage {
+    /// The total size in bytes of all live buffers and images created on the device.
+    ///
+    /// Buffers that wrap caller-owned host memory are not counted.
+    pub total_bytes: usize,
+    /// The bytes allocated by live buffers and images, broken down by debug label.
+    ///
+    /// Allocations without a label are counted under `None`.
+    pub by_label: HashMap<Option<String>, usize>,
+    /// The budget set with `Device::set_memory_budget()`, if any.
+    pub budget: Option<usize>,
+    /// The driver's own view of the device's memory, if it reports one.
+    pub driver: Option<DriverMemoryInfo>,
+}
+
+/// Memory statistics reported by the driver.
+///
+/// Each field is `None` if the driver does not report it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DriverMemoryInfo {
+    /// The total amount of memory on the device, in bytes.
+    pub total_bytes: Option<u64>,
+    /// The amount of memory on the device still available, in bytes.
+    pub available_bytes: Option<u64>,
+}
+
+// The allocations made on one device, shared with the buffers and images that it creates.
+#[doc(hidden)]
+pub struct MemoryTracker {
+    state: Mutex<MemoryState>,
+}
+
+struct MemoryState {
+    total_bytes: usize,
+    by_label: HashMap<Option<String>, usize>,
+    budget: Option<usize>,
+}
+
+// The memory charged to a device by one buffer or image. Dropping this releases the charge.
+#[doc(hidden)]
+pub struct TrackedMemory {
+    tracker: Arc<MemoryTracker>,
+    size: usize,
+    label: Option<String>,
+}
+
+impl MemoryTracker {
+    #[doc(hidden)]
+    pub fn new() -> Arc<MemoryTracker> {
+        Arc::new(MemoryTracker {
+            state: Mutex::new(MemoryState {
+                total_bytes: 0,
+                by_label: HashMap::new(),
+                budget: None,
+            }),
+        })
+    }
+
+    #[doc(hidden)]
+    pub fn set_budget(&self, budget: Option<usize>) {
+        self.state.lock().unwrap().budget = budget
+    }
+
+    // Charges `size` bytes to the device, or fails with `Error::OutOfMemory` if that would exceed
+    // the budget.
+    #[doc(hidden)]
+    pub fn reserve(this: &Arc<MemoryTracker>, size: usize) -> Result<TrackedMemory, Error> {
+        let mut state = this.state.lock().unwrap();
+        if let Some(budget) = state.budget {
+            if state.total_bytes.saturating_add(size) > budget {
+                return Err(Error::OutOfMemory)
+            }
+        }
+
+        state.total_bytes += size;
+        *state.by_label.entry(None).or_insert(0) += size;
+        Ok(TrackedMemory {
+            tracker: this.clone(),
+            size: size,
+            label: None,
+        })
+    }
+
+    #[doc(hidden)]
+    pub fn usage(&self, driver: Option<DriverMemoryInfo>) -> MemoryUsage {
+        let state = self.state.lock().unwrap();
+        MemoryUsage {
+            total_bytes: state.total_bytes,
+            by_label: state.by_label.clone(),
+            budget: state.budget,
+            driver: driver,
+        }
+    }
+}
+
+impl MemoryState {
+    fn release(&mut self, size: usize, label: &Option<String>) {
+        self.total_bytes -= size;
+        let remove = match self.by_label.get_mut(label) {
+            Some(label_bytes) => {
+                *label_bytes -= size;
+                *label_bytes == 0
+            }
+            None => false,
+        };
+        if remove {
+            self.by_label.remove(label);
+        }
+    }
+}
+
+impl TrackedMemory {
+    #[doc(hidden)]
+    pub fn set_label(&mut self, label: Option<&str>) {
+        let mut state = self.tracker.state.lock().unwrap();
+        state.release(self.size, &self.label);
+        self.label = label.map(|label| label.to_owned());
+        state.total_bytes += self.size;
+        *state.by_label.entry(self.label.clone()).or_insert(0) += self.size;
+    }
+}
+
+impl Drop for TrackedMemory {
+    fn drop(&mut self) {
+        self.tracker.state.lock().unwrap().release(self.size, &self.label)
+    }
+}
@@ -11,6 +11,10 @@
 //! Objects that can be used to query how long GPU operations took.
 
 use error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
 /// An object that can be used to query how long GPU operations took.
 pub struct ProfileEvent {
@@ -22,6 +26,59 @@ pub struct ProfileEvent {
 pub struct ProfileEventFunctions {
     pub destroy: unsafe extern "Rust" fn(this: &ProfileEvent),
     pub time_elapsed: extern "Rust" fn(this: &ProfileEvent) -> Result<u64, Error>,
+    pub timestamps: extern "Rust" fn(this: &ProfileEvent) -> Result<Timestamps, Error>,
+    pub poll_time_elapsed: extern "Rust" fn(this: &ProfileEvent, waker: &Waker)
+                                            -> Poll<Result<u64, Error>>,
+    pub cancel_poll_time_elapsed: extern "Rust" fn(this: &ProfileEvent),
+}
+
+static NULL_PROFILE_EVENT_FUNCTIONS: ProfileEventFunctions = ProfileEventFunctions {
+    destroy: null_destroy,
+    time_elapsed: null_time_elapsed,
+    timestamps: null_timestamps,
+    poll_time_elapsed: null_poll_time_elapsed,
+    cancel_poll_time_elapsed: null_cancel_poll_time_elapsed,
+};
+
+/// The points in time at which an operation passed through each stage of execution.
+///
+/// All values are in nanoseconds on the GPU's clock. Use `ClockCalibration` to convert them to
+/// host time.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Timestamps {
+    /// When the operation was submitted to the queue by the host.
+    ///
+    /// The OpenGL backend estimates this from the host clock, since reading the GPU clock
+    /// directly would stall the CPU.
+    pub queued: u64,
+    /// When the operation was submitted to the GPU.
+    ///
+    /// The OpenGL backend has no separate submission step and reports the same value as
+    /// `queued`.
+    pub submitted: u64,
+    /// When the GPU started executing the operation.
+    pub start: u64,
+    /// When the GPU finished executing the operation.
+    pub end: u64,
+}
+
+/// A GPU timestamp paired with the host time at which it was taken.
+///
+/// This is returned by `Device::calibrate_clock()` and can be used to place GPU timestamps on the
+/// host timeline. The two clocks drift apart over time, so calibrate periodically.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClockCalibration {
+    /// The GPU timestamp in nanoseconds.
+    pub gpu_timestamp: u64,
+    /// The host time corresponding to `gpu_timestamp`.
+    pub host_time: Instant,
+}
+
+/// A future that resolves to the time that an operation took once it has completed.
+///
+/// This is returned by `ProfileEvent::time_elapsed_async()`.
+pub struct TimeElapsedFuture<'a> {
+    event: &'a ProfileEvent,
 }
 
 impl Drop for ProfileEvent {
@@ -49,6 +106,25 @@ impl ProfileEvent {
         self.data
     }
 
+    /// Returns a profile event that records no timing information.
+    ///
+    /// This is what commands return when profiling is disabled. All queries on it fail. Creating
+    /// and dropping one costs nothing.
+    #[inline]
+    pub fn null() -> ProfileEvent {
+        ProfileEvent {
+            data: 0,
+            functions: &NULL_PROFILE_EVENT_FUNCTIONS,
+        }
+    }
+
+    /// Returns true if this is a null profile event.
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.functions as *const ProfileEventFunctions ==
+            &NULL_PROFILE_EVENT_FUNCTIONS as *const ProfileEventFunctions
+    }
+
     /// Returns the time that this operation took in nanoseconds.
     ///
     /// If the operation has not yet completed, this function blocks until it completes.
@@ -56,5 +132,91 @@ impl ProfileEvent {
     pub fn time_elapsed(&self) -> Result<u64, Error> {
         (self.functions.time_elapsed)(self)
     }
+
+    /// Returns the timestamps at which this operation was queued, submitted, started, and ended.
+    ///
+    /// If the operation has not yet completed, this function blocks until it completes.
+    #[inline]
+    pub fn timestamps(&self) -> Result<Timestamps, Error> {
+        (self.functions.timestamps)(self)
+    }
+
+    /// Returns a future that resolves to the time that this operation took in nanoseconds,
+    /// without blocking the CPU.
+    ///
+    /// The same caveats as for `SyncEvent::wait_async()` apply.
+    #[inline]
+    pub fn time_elapsed_async<'a>(&'a self) -> TimeElapsedFuture<'a> {
+        TimeElapsedFuture {
+            event: self,
+        }
+    }
+}
+
+unsafe fn null_destroy(_: &ProfileEvent) {}
+
+fn null_time_elapsed(_: &ProfileEvent) -> Result<u64, Error> {
+    Err(Error::Failed)
+}
+
+fn null_timestamps(_: &ProfileEvent) -> Result<Timestamps, Error> {
+    Err(Error::Failed)
+}
+
+fn null_poll_time_elapsed(_: &ProfileEvent, _: &Waker) -> Poll<Result<u64, Error>> {
+    Poll::Ready(Err(Error::Failed))
+}
+
+fn null_cancel_poll_time_elapsed(_: &ProfileEvent) {}
+
+impl Timestamps {
+    /// Returns the time in nanoseconds that the operation spent waiting between being queued and
+    /// starting execution.
+    #[inline]
+    pub fn queue_latency(&self) -> u64 {
+        self.start.saturating_sub(self.queued)
+    }
+
+    /// Returns the time in nanoseconds that the operation took to execute.
+    #[inline]
+    pub fn execution_time(&self) -> u64 {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+impl ClockCalibration {
+    /// Converts a GPU timestamp, such as one from `Timestamps`, to the corresponding host time.
+    ///
+    /// Returns `None` if that time cannot be represented as an `Instant`, which can happen for
+    /// timestamps from before the host clock's epoch.
+    pub fn host_time(&self, gpu_timestamp: u64) -> Option<Instant> {
+        if gpu_timestamp >= self.gpu_timestamp {
+            self.host_time.checked_add(Duration::from_nanos(gpu_timestamp - self.gpu_timestamp))
+        } else {
+            self.host_time.checked_sub(Duration::from_nanos(self.gpu_timestamp - gpu_timestamp))
+        }
+    }
+
+    /// Returns the signed number of nanoseconds from the calibration point to `gpu_timestamp`.
+    #[inline]
+    pub fn nanos_since(&self, gpu_timestamp: u64) -> i64 {
+        gpu_timestamp.wrapping_sub(self.gpu_timestamp) as i64
+    }
+}
+
+impl<'a> Future for TimeElapsedFuture<'a> {
+    type Output = Result<u64, Error>;
+
+    #[inline]
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<u64, Error>> {
+        (self.event.functions.poll_time_elapsed)(self.event, context.waker())
+    }
+}
+
+impl<'a> Drop for TimeElapsedFuture<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        (self.event.functions.cancel_poll_time_elapsed)(self.event)
+    }
 }
 
@@ -0,0 +1,264 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Collection of GPU timings under named scopes, with export to the Chrome trace format.
+//!
+//! Record profile events with `Profiler::record()` as commands are submitted, and call
+//! `Profiler::resolve()` once the GPU has finished with them. The result can be written with
+//! `Profiler::write_chrome_trace()` and loaded into `chrome://tracing` or Perfetto, where CPU
+//! scopes and the GPU commands submitted within them are shown on separate tracks.
+
+use device::Device;
+use error::Error;
+use profile_event::{ClockCalibration, ProfileEvent};
+use std::cmp;
+use std::io::{self, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+const CPU_TRACK: u32 = 0;
+const GPU_TRACK: u32 = 1;
+
+/// Collects GPU timings under named, nested scopes.
+pub struct Profiler {
+    epoch: Instant,
+    calibration: ClockCalibration,
+    scopes: Vec<Scope>,
+    open_scopes: Vec<usize>,
+    pending_events: Vec<PendingEvent>,
+    gpu_spans: Vec<GpuSpan>,
+}
+
+struct Scope {
+    label: String,
+    cpu_start: Instant,
+    cpu_end: Option<Instant>,
+    gpu_range: Option<(u64, u64)>,
+}
+
+struct PendingEvent {
+    label: String,
+    scopes: Vec<usize>,
+    event: ProfileEvent,
+}
+
+struct GpuSpan {
+    label: String,
+    queued: u64,
+    start: u64,
+    end: u64,
+}
+
+impl Profiler {
+    /// Creates a new profiler for commands submitted on queues of the given device.
+    ///
+    /// This calibrates the GPU clock against the host clock, so it may block until the GPU has
+    /// caught up with previously submitted work.
+    pub fn new(device: &Device) -> Result<Profiler, Error> {
+        Ok(Profiler {
+            epoch: Instant::now(),
+            calibration: try!(device.calibrate_clock()),
+            scopes: vec![],
+            open_scopes: vec![],
+            pending_events: vec![],
+            gpu_spans: vec![],
+        })
+    }
+
+    /// Opens a new scope with the given label, nested inside the current one.
+    ///
+    /// Every call must be balanced by a call to `end_scope()`.
+    pub fn begin_scope(&mut self, label: &str) {
+        self.open_scopes.push(self.scopes.len());
+        self.scopes.push(Scope {
+            label: label.to_owned(),
+            cpu_start: Instant::now(),
+            cpu_end: None,
+            gpu_range: None,
+        })
+    }
+
+    /// Closes the innermost open scope.
+    ///
+    /// Panics if there is no open scope.
+    pub fn end_scope(&mut self) {
+        let scope = self.open_scopes.pop().expect("end_scope() called without an open scope");
+        self.scopes[scope].cpu_end = Some(Instant::now())
+    }
+
+    /// Runs `f` inside a new scope with the given label.
+    pub fn scope<F, R>(&mut self, label: &str, f: F) -> R where F: FnOnce(&mut Profiler) -> R {
+        self.begin_scope(label);
+        let result = f(self);
+        self.end_scope();
+        result
+    }
+
+    /// Records the profile event of a command under the given label, inside the current scope.
+    ///
+    /// The event isn't queried until `resolve()` is called. Null profile events, which come from
+    /// queues with profiling disabled, are ignored.
+    pub fn record(&mut self, label: &str, event: ProfileEvent) {
+        if event.is_null() {
+            return
+        }
+
+        self.pending_events.push(PendingEvent {
+            label: label.to_owned(),
+            scopes: self.open_scopes.clone(),
+            event: event,
+        })
+    }
+
+    /// Queries the timings of all recorded profile events.
+    ///
+    /// This blocks until the GPU has finished executing the corresponding commands. If querying
+    /// an event fails, that event and the ones after it stay pending, so that a later call can
+    /// resolve them.
+    pub fn resolve(&mut self) -> Result<(), Error> {
+        let mut pending_events = mem::replace(&mut self.pending_events, vec![]).into_iter();
+        while let Some(pending_event) = pending_events.next() {
+            let timestamps = match pending_event.event.timestamps() {
+                Ok(timestamps) => timestamps,
+                Err(err) => {
+                    self.pending_events.push(pending_event);
+                    self.pending_events.extend(pending_events);
+                    return Err(err)
+                }
+            };
+            for &scope in &pending_event.scopes {
+                let scope = &mut self.scopes[scope];
+                scope.gpu_range = Some(match scope.gpu_range {
+                    None => (timestamps.start, timestamps.end),
+                    Some((start, end)) => {
+                        (cmp::min(start, timestamps.start), cmp::max(end, timestamps.end))
+                    }
+                })
+            }
+
+            self.gpu_spans.push(GpuSpan {
+                label: pending_event.label,
+                queued: timestamps.queued,
+                start: timestamps.start,
+                end: timestamps.end,
+            })
+        }
+        Ok(())
+    }
+
+    /// Writes all closed scopes and resolved GPU timings as a JSON trace in the Chrome trace event
+    /// format.
+    ///
+    /// CPU scopes appear on one track; on a second track, each scope spans the GPU commands
+    /// submitted within it. Events that have been recorded but not yet resolved are omitted.
+    pub fn write_chrome_trace<W>(&self, writer: &mut W) -> io::Result<()> where W: Write {
+        try!(writer.write_all(b"{\"traceEvents\":[\n"));
+        try!(write_track_name(writer, CPU_TRACK, "CPU"));
+        try!(writer.write_all(b",\n"));
+        try!(write_track_name(writer, GPU_TRACK, "GPU"));
+
+        for scope in &self.scopes {
+            if let Some(cpu_end) = scope.cpu_end {
+                try!(writer.write_all(b",\n"));
+                try!(write_span(writer,
+                                CPU_TRACK,
+                                &scope.label,
+                                self.micros_since_epoch(scope.cpu_start),
+                                self.micros_since_epoch(cpu_end),
+                                None));
+            }
+
+            if let Some((gpu_start, gpu_end)) = scope.gpu_range {
+                try!(writer.write_all(b",\n"));
+                try!(write_span(writer,
+                                GPU_TRACK,
+                                &scope.label,
+                                self.gpu_micros_since_epoch(gpu_start),
+                                self.gpu_micros_since_epoch(gpu_end),
+                                None));
+            }
+        }
+
+        for span in &self.gpu_spans {
+            try!(writer.write_all(b",\n"));
+            try!(write_span(writer,
+                            GPU_TRACK,
+                            &span.label,
+                            self.gpu_micros_since_epoch(span.start),
+                            self.gpu_micros_since_epoch(span.end),
+                            Some(span.start.saturating_sub(span.queued))));
+        }
+
+        writer.write_all(b"\n]}\n")
+    }
+
+    fn micros_since_epoch(&self, time: Instant) -> f64 {
+        if time >= self.epoch {
+            duration_to_micros(time - self.epoch)
+        } else {
+            -duration_to_micros(self.epoch - time)
+        }
+    }
+
+    // Timestamps may predate the calibration point, so work with signed offsets rather than
+    // converting to `Instant`.
+    fn gpu_micros_since_epoch(&self, gpu_timestamp: u64) -> f64 {
+        self.micros_since_epoch(self.calibration.host_time) +
+            self.calibration.nanos_since(gpu_timestamp) as f64 / 1_000.0
+    }
+}
+
+fn duration_to_micros(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1_000_000.0 + duration.subsec_nanos() as f64 / 1_000.0
+}
+
+fn write_track_name<W>(writer: &mut W, track: u32, name: &str) -> io::Result<()> where W: Write {
+    try!(write!(writer, "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":0,\"tid\":{},", track));
+    try!(writer.write_all(b"\"args\":{\"name\":"));
+    try!(write_json_string(writer, name));
+    writer.write_all(b"}}")
+}
+
+fn write_span<W>(writer: &mut W,
+                 track: u32,
+                 label: &str,
+                 start: f64,
+                 end: f64,
+                 queue_latency: Option<u64>)
+                 -> io::Result<()>
+                 where W: Write {
+    try!(writer.write_all(b"{\"name\":"));
+    try!(write_json_string(writer, label));
+    try!(write!(writer,
+                ",\"ph\":\"X\",\"pid\":0,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}",
+                track,
+                start,
+                end - start));
+    if let Some(queue_latency) = queue_latency {
+        try!(write!(writer, ",\"args\":{{\"queue_latency_ns\":{}}}", queue_latency));
+    }
+    writer.write_all(b"}")
+}
+
+fn write_json_string<W>(writer: &mut W, string: &str) -> io::Result<()> where W: Write {
+    try!(writer.write_all(b"\""));
+    for character in string.chars() {
+        match character {
+            '"' => try!(writer.write_all(b"\\\"")),
+            '\\' => try!(writer.write_all(b"\\\\")),
+            '\n' => try!(writer.write_all(b"\\n")),
+            character if (character as u32) < 0x20 => {
+                try!(write!(writer, "\\u{:04x}", character as u32))
+            }
+            character => try!(write!(writer, "{}", character)),
+        }
+    }
+    writer.write_all(b"\"")
+}
@@ -10,6 +10,9 @@
 
 //! Programs to be run on the GPU.
 
+use error::Error;
+use queue::Uniform;
+
 /// A program to be run on the GPU.
 pub struct Program {
     data: usize,
@@ -19,6 +22,8 @@ pub struct Program {
 #[doc(hidden)]
 pub struct ProgramFunctions {
     pub destroy: unsafe extern "Rust" fn(this: &Program),
+    pub uniform_index: extern "Rust" fn(this: &Program, name: &str, uniform: &Uniform)
+                                        -> Result<u32, Error>,
 }
 
 impl Drop for Program {
@@ -44,5 +49,35 @@ impl Program {
     pub fn data(&self) -> usize {
         self.data
     }
+
+    /// Returns the index that `uniform` must be passed at to set the program argument named
+    /// `name`.
+    ///
+    /// What the index means depends on the backend and on the kind of uniform, which is why the
+    /// uniform is needed to look it up. In OpenCL, it is the index of the kernel argument with the
+    /// given name. In OpenGL, it is the binding point of the shader storage block named `name`
+    /// for buffers, the image unit of the image uniform for images, the binding point of the
+    /// uniform block for raw bytes, and the location of the uniform for everything else.
+    ///
+    /// Returns `Error::UniformNotFound` if the program has no such argument. Returns
+    /// `Error::UniformMismatch` if the argument cannot be set from this kind of uniform; OpenCL
+    /// checks this against the kernel's argument info, while OpenGL only finds arguments in the
+    /// interface that matches the uniform.
+    #[inline]
+    pub fn uniform_index(&self, name: &str, uniform: &Uniform) -> Result<u32, Error> {
+        (self.functions.uniform_index)(self, name, uniform)
+    }
+
+    /// Looks up the index of each named uniform with `uniform_index()`, failing on the first
+    /// argument that is missing or mismatched.
+    pub fn uniform_indices<'a>(&self, uniforms: &[(&str, Uniform<'a>)])
+                               -> Result<Vec<(u32, Uniform<'a>)>, Error> {
+        let mut indexed_uniforms = Vec::with_capacity(uniforms.len());
+        for &(name, ref uniform) in uniforms {
+            let index = try!(self.uniform_index(name, uniform));
+            indexed_uniforms.push((index, *uniform))
+        }
+        Ok(indexed_uniforms)
+    }
 }
 
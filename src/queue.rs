@@ -10,11 +10,13 @@
 
 //! Queues on which compute jobs can be submitted.
 
-use buffer::Buffer;
+use buffer::{Buffer, BufferSlice, MemoryLocation};
 use error::Error;
 use image::{Color, Image};
+use kernel_args::KernelArgs;
 use profile_event::ProfileEvent;
 use program::Program;
+use sampler::Sampler;
 use sync_event::SyncEvent;
 
 /// A queue on which compute jobs can be submitted.
@@ -46,18 +48,107 @@ pub struct QueueFunctions {
                                              events: &[SyncEvent])
                                              -> Result<ProfileEvent, Error>,
     pub submit_sync_event: extern "Rust" fn(this: &Queue) -> Result<SyncEvent, Error>,
+    pub submit_barrier: extern "Rust" fn(this: &Queue, scope: BarrierScope) -> Result<(), Error>,
+}
+
+/// Options that control how a queue executes the commands submitted to it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct QueueOptions {
+    /// Whether the GPU may execute commands on this queue in a different order than the one in
+    /// which they were submitted.
+    ///
+    /// If this is set, dependencies between commands must be expressed explicitly with sync
+    /// events. Backends that cannot reorder commands ignore this flag.
+    pub out_of_order: bool,
+    /// Whether the profiling events returned by commands on this queue record timing
+    /// information.
+    ///
+    /// Profiling has a cost for every command, so it is off by default. If it is not set,
+    /// commands on this queue return null profile events (see `ProfileEvent::null()`). To profile
+    /// only some commands, submit them on a separate queue with profiling enabled.
+    pub profiling: bool,
+    /// Whether the queue inserts memory barriers between commands automatically.
+    ///
+    /// If this is set, the queue tracks which resources programs may have written to and issues
+    /// the narrowest barrier needed before a later command accesses one of them. If it is not set,
+    /// no barriers are issued implicitly, and it is up to the caller to order memory accesses with
+    /// `Queue::submit_barrier()`. Backends whose commands are always coherent with one another
+    /// ignore this flag.
+    pub automatic_barriers: bool,
+}
+
+/// The kinds of memory access that a barrier makes prior writes by programs visible to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BarrierScope {
+    /// Reads and writes of buffers by subsequent programs.
+    Storage,
+    /// Reads and writes of images by subsequent programs.
+    Image,
+    /// Copies, clears, and other transfer commands on the queue.
+    Transfer,
+    /// Reads of buffers and images back to main memory.
+    HostRead,
+    /// All of the above.
+    All,
 }
 
 /// An argument to a program.
+#[derive(Clone, Copy)]
 pub enum Uniform<'a> {
     /// A reference to a GPU-side memory buffer.
     Buffer(&'a Buffer),
+    /// A reference to a range of a GPU-side memory buffer (see `Buffer::slice()`).
+    BufferSlice(BufferSlice<'a>),
+    /// A reference to a small, read-only GPU-side memory buffer, bound as a uniform buffer in
+    /// OpenGL and passed as a `__constant` pointer in OpenCL.
+    ///
+    /// This is often faster than `Buffer` for parameters that every invocation reads. Create such
+    /// buffers with `Protection::Constant`.
+    ConstantBuffer(&'a Buffer),
     /// A reference to an image on the GPU.
     Image(&'a Image),
+    /// A reference to an image on the GPU, read through a sampler with hardware filtering.
+    ///
+    /// In OpenGL, the index is the texture unit of a `sampler2DRect` uniform. In OpenCL, this
+    /// uniform sets two kernel arguments: the image is passed as the argument at the index and the
+    /// sampler as the argument at the index plus one, so the kernel must declare its `sampler_t`
+    /// parameter immediately after the image. When passing uniforms by name, use the name of the
+    /// image argument.
+    SampledImage(&'a Image, &'a Sampler),
     /// A 32-bit unsigned integer value.
     U32(u32),
     /// A vector of 4 32-bit unsigned integers.
     UVec4([u32; 4]),
+    /// A 32-bit signed integer value.
+    I32(i32),
+    /// A vector of 2 32-bit signed integers.
+    IVec2([i32; 2]),
+    /// A vector of 3 32-bit signed integers.
+    IVec3([i32; 3]),
+    /// A vector of 4 32-bit signed integers.
+    IVec4([i32; 4]),
+    /// A 32-bit floating point value.
+    F32(f32),
+    /// A vector of 2 32-bit floating point values.
+    Vec2([f32; 2]),
+    /// A vector of 3 32-bit floating point values.
+    Vec3([f32; 3]),
+    /// A vector of 4 32-bit floating point values.
+    Vec4([f32; 4]),
+    /// A 4x4 matrix of 32-bit floating point values, in column-major order.
+    Mat4([[f32; 4]; 4]),
+    /// Raw bytes, for arguments of struct type.
+    ///
+    /// The bytes must match the layout of the struct as the shading language sees it. In OpenCL
+    /// this is the layout of the struct in the kernel argument list. In OpenGL the bytes are
+    /// uploaded to a uniform block (with `std140` layout) bound at the argument index.
+    Bytes(&'a [u8]),
+    /// A block of workgroup-local memory of the given size in bytes, for `__local` pointer
+    /// arguments to OpenCL kernels.
+    ///
+    /// GLSL can only declare `shared` memory with a size fixed at compile time, so this is
+    /// unsupported on OpenGL. Declare a `shared` array in the shader instead.
+    LocalMemory(usize),
 }
 
 impl Drop for Queue {
@@ -68,6 +159,17 @@ impl Drop for Queue {
     }
 }
 
+impl Default for QueueOptions {
+    #[inline]
+    fn default() -> QueueOptions {
+        QueueOptions {
+            out_of_order: false,
+            profiling: false,
+            automatic_barriers: true,
+        }
+    }
+}
+
 impl Queue {
     #[doc(hidden)]
     #[inline]
@@ -111,7 +213,7 @@ impl Queue {
     ///   argument (see `Uniform`).
     ///
     /// * `events` is a list of sync events that must complete before execution of this program can
-    ///   begin.
+    ///   begin. These may come from any queue on the same device.
     ///
     /// Returns a profiling event that can be used to time the execution of this program.
     #[inline]
@@ -124,6 +226,39 @@ impl Queue {
         (self.functions.submit_compute)(self, program, num_groups, uniforms, events)
     }
 
+    /// Instructs the GPU to execute the given program, passing uniforms by argument name.
+    ///
+    /// This is like `submit_compute()`, except that each uniform is paired with the name of the
+    /// program argument it sets, which is resolved with `Program::uniform_index()`. This allows
+    /// the same call to drive the OpenCL and GLSL versions of a program, as long as their
+    /// arguments have the same names.
+    pub fn submit_compute_by_name(&self,
+                                  program: &Program,
+                                  num_groups: &[u32],
+                                  uniforms: &[(&str, Uniform)],
+                                  events: &[SyncEvent])
+                                  -> Result<ProfileEvent, Error> {
+        let indexed_uniforms = try!(program.uniform_indices(uniforms));
+        self.submit_compute(program, num_groups, &indexed_uniforms, events)
+    }
+
+    /// Instructs the GPU to execute the given program, with arguments taken from the fields of a
+    /// struct (see the `kernel_args` module).
+    ///
+    /// The fields are checked against the program's arguments as in `KernelArgs::check()`, and
+    /// nothing is submitted unless every field matches.
+    #[inline]
+    pub fn submit_compute_with_args<A>(&self,
+                                       program: &Program,
+                                       num_groups: &[u32],
+                                       args: &A,
+                                       events: &[SyncEvent])
+                                       -> Result<ProfileEvent, Error>
+                                       where A: KernelArgs {
+        let indexed_uniforms = try!(program.uniform_indices(&args.uniforms()));
+        self.submit_compute(program, num_groups, &indexed_uniforms, events)
+    }
+
     /// Instructs the GPU to clear the given image to a solid color.
     ///
     /// * `image` specifies the image to clear.
@@ -149,7 +284,10 @@ impl Queue {
     ///
     /// * `events` is a list of sync events that must complete before this operation can begin.
     ///
-    /// Returns a profiling event that can be used to query how long this operation took.
+    /// Returns a profiling event that can be used to query how long this operation took,
+    /// `Error::OutOfBounds` if the range to read extends past the end of the buffer, or
+    /// `Error::Unsupported` if the buffer is in `MemoryLocation::Upload`, which the CPU only
+    /// writes.
     ///
     /// This operation blocks until completion.
     #[inline]
@@ -159,14 +297,32 @@ impl Queue {
                               start: usize,
                               events: &[SyncEvent])
                               -> Result<ProfileEvent, Error> {
+        if buffer.memory_location() == MemoryLocation::Upload {
+            return Err(Error::Unsupported("reading back upload buffers"))
+        }
+        try!(buffer.check_range(&(start..start.saturating_add(dest.len()))));
         (self.functions.submit_read_buffer)(self, dest, buffer, start, events)
     }
 
     /// Returns a sync event that can be used to wait until the GPU has finished executing all
     /// commands up to the point at which this is called.
+    ///
+    /// The event can be passed to commands submitted on other queues in order to make them wait
+    /// for the commands on this one.
     #[inline]
     pub fn submit_sync_event(&self) -> Result<SyncEvent, Error> {
         (self.functions.submit_sync_event)(self)
     }
+
+    /// Ensures that writes made by programs previously submitted to this queue are visible to
+    /// the kinds of access in `scope` performed by commands submitted after this one.
+    ///
+    /// This is only needed on queues created without automatic barriers (see
+    /// `QueueOptions::automatic_barriers`). It does not order commands across queues; use sync
+    /// events for that.
+    #[inline]
+    pub fn submit_barrier(&self, scope: BarrierScope) -> Result<(), Error> {
+        (self.functions.submit_barrier)(self, scope)
+    }
 }
 
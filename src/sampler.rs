@@ -0,0 +1,75 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Samplers, which control how images are read when sampled with filtering.
+
+/// A sampler, which controls how an image bound with `Uniform::SampledImage` is read.
+pub struct Sampler {
+    data: usize,
+    functions: &'static SamplerFunctions,
+}
+
+#[doc(hidden)]
+pub struct SamplerFunctions {
+    pub destroy: unsafe extern "Rust" fn(this: &Sampler),
+}
+
+/// How texels are combined when an image is sampled between texel centers.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    /// The nearest texel is returned.
+    Nearest,
+    /// The four nearest texels are blended together (bilinear filtering).
+    Linear,
+}
+
+/// What sampling an image outside its bounds returns.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AddressMode {
+    /// Coordinates are clamped to the edge of the image.
+    ClampToEdge,
+    /// Coordinates outside the image return the border color (transparent black).
+    ClampToBorder,
+    /// The image is tiled.
+    ///
+    /// This requires normalized coordinates and is unsupported on OpenGL, whose images are
+    /// rectangle textures.
+    Repeat,
+    /// The image is tiled, with every other tile mirrored.
+    ///
+    /// This requires normalized coordinates and is unsupported on OpenGL, whose images are
+    /// rectangle textures.
+    MirroredRepeat,
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            (self.functions.destroy)(self)
+        }
+    }
+}
+
+impl Sampler {
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn from_raw_data(data: usize, functions: &'static SamplerFunctions) -> Sampler {
+        Sampler {
+            data: data,
+            functions: functions,
+        }
+    }
+
+    #[doc(hidden)]
+    #[inline]
+    pub fn data(&self) -> usize {
+        self.data
+    }
+}
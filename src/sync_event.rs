@@ -11,6 +11,10 @@
 //! Events (a.k.a. fences) that can be waited on.
 
 use error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
 
 /// An event (a.k.a. fence) that can be waited on.
 pub struct SyncEvent {
@@ -22,6 +26,34 @@ pub struct SyncEvent {
 pub struct SyncEventFunctions {
     pub destroy: unsafe extern "Rust" fn(this: &SyncEvent),
     pub wait: extern "Rust" fn(this: &SyncEvent) -> Result<(), Error>,
+    pub wait_timeout: extern "Rust" fn(this: &SyncEvent, timeout: Duration) -> Result<(), Error>,
+    pub status: extern "Rust" fn(this: &SyncEvent) -> Result<Status, Error>,
+    pub signal: extern "Rust" fn(this: &SyncEvent) -> Result<(), Error>,
+    pub poll: extern "Rust" fn(this: &SyncEvent, waker: &Waker) -> Poll<Result<(), Error>>,
+    pub cancel_poll: extern "Rust" fn(this: &SyncEvent),
+}
+
+/// The execution status of the commands that a sync event waits on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    /// The commands have been submitted but have not yet started executing.
+    Queued,
+    /// The commands are executing on the GPU.
+    ///
+    /// The OpenGL backend cannot distinguish queued commands from running ones and reports this
+    /// status for both.
+    Running,
+    /// The commands have finished executing.
+    Complete,
+    /// The commands were abnormally terminated.
+    Error,
+}
+
+/// A future that resolves when a sync event has occurred.
+///
+/// This is returned by `SyncEvent::wait_async()`.
+pub struct SyncEventFuture<'a> {
+    event: &'a SyncEvent,
 }
 
 impl Drop for SyncEvent {
@@ -53,5 +85,57 @@ impl SyncEvent {
     pub fn wait(&self) -> Result<(), Error> {
         (self.functions.wait)(self)
     }
+
+    /// Blocks the CPU until this event has occurred or the given amount of time has passed,
+    /// whichever comes first.
+    ///
+    /// Returns `Error::Timeout` if the event did not occur in time.
+    #[inline]
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        (self.functions.wait_timeout)(self, timeout)
+    }
+
+    /// Returns the current status of the commands that this event waits on, without blocking.
+    #[inline]
+    pub fn status(&self) -> Result<Status, Error> {
+        (self.functions.status)(self)
+    }
+
+    /// Marks a user event created with `Device::create_user_event()` as having occurred,
+    /// releasing any commands that are waiting on it.
+    ///
+    /// A user event can only be signaled once. Signaling any other kind of event fails.
+    #[inline]
+    pub fn signal(&self) -> Result<(), Error> {
+        (self.functions.signal)(self)
+    }
+
+    /// Returns a future that resolves when this event has occurred, without blocking the CPU.
+    ///
+    /// The queue that this event was submitted on must be flushed, or the future may never
+    /// resolve. With the OpenGL backend, the future must be polled and dropped on the thread that
+    /// owns the OpenGL context.
+    #[inline]
+    pub fn wait_async<'a>(&'a self) -> SyncEventFuture<'a> {
+        SyncEventFuture {
+            event: self,
+        }
+    }
+}
+
+impl<'a> Future for SyncEventFuture<'a> {
+    type Output = Result<(), Error>;
+
+    #[inline]
+    fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<Result<(), Error>> {
+        (self.event.functions.poll)(self.event, context.waker())
+    }
+}
+
+impl<'a> Drop for SyncEventFuture<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        (self.event.functions.cancel_poll)(self.event)
+    }
 }
 
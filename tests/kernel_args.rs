@@ -0,0 +1,51 @@
+// Copyright 2017 The Servo Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+extern crate compute_shader;
+#[macro_use]
+extern crate compute_shader_derive;
+
+use compute_shader::kernel_args::KernelArgs;
+use compute_shader::queue::Uniform;
+
+#[derive(KernelArgs)]
+struct Args<'a> {
+    #[uniform(name = "kSeed")]
+    seed: u32,
+    origin: [f32; 2],
+    bytes: &'a [u8],
+}
+
+#[test]
+fn uniforms_follow_field_order_and_names() {
+    let bytes = [1, 2, 3];
+    let args = Args {
+        seed: 7,
+        origin: [0.5, 1.5],
+        bytes: &bytes,
+    };
+
+    let uniforms = args.uniforms();
+    let names: Vec<_> = uniforms.iter().map(|&(name, _)| name).collect();
+    assert_eq!(names, ["kSeed", "origin", "bytes"]);
+
+    match uniforms[0].1 {
+        Uniform::U32(7) => {}
+        _ => panic!("`seed` should be passed as `Uniform::U32`"),
+    }
+    match uniforms[1].1 {
+        Uniform::Vec2(origin) => assert_eq!(origin, [0.5, 1.5]),
+        _ => panic!("`origin` should be passed as `Uniform::Vec2`"),
+    }
+    match uniforms[2].1 {
+        Uniform::Bytes(passed) => assert_eq!(passed, &bytes),
+        _ => panic!("`bytes` should be passed as `Uniform::Bytes`"),
+    }
+}